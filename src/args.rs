@@ -5,24 +5,77 @@ use gfunc::{simple_envpath};
 use std::path::{PathBuf};
 
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    ///'axbind ROOT'
+    Render,
+    ///'axbind check ROOT'
+    Check,
+    ///'axbind switch NAME ROOT' ('-' as NAME switches back to the previous scheme)
+    Switch(String),
+    ///'axbind current'
+    Current,
+    ///'axbind watch ROOT'
+    Watch,
+}
+///Applied to every (non-pinned) tag group's scheme spec for this run.
+#[derive(Debug, Default)]
+pub struct SchemeOverride {
+    ///'--scheme NAME'; replaces the scheme name.
+    pub scheme: Option<String>,
+    ///'--remap A,B'; applied after the tag group's own remaps.
+    pub remaps: Vec<String>,
+    ///'--function A,B'; applied after the tag group's own functions.
+    pub functions: Vec<String>,
+}
 #[derive(Debug)]
 pub struct ProgramOptions {
+    pub command: Command,
     pub config_paths: Vec<PathBuf>,
     pub root_dir: PathBuf,
     pub tagdir_path: PathBuf,
+    pub dry_run: bool,
+    pub diff: bool,
+    ///Print the merged config (and where each setting came from) instead of rendering.
+    pub show_config: bool,
+    pub scheme_override: SchemeOverride,
+    ///'--variant NAME'; used by tag groups that don't pick a 'scheme.variant', if their scheme has it.
+    pub variant: Option<String>,
 }
 pub fn read_runinfo(runinfo: RunInfo) -> ProgramOptions {
-    let valid_singlet_opts: [(&'static str, Option<char>); 0] = [];
-    let valid_valued_opts: [&'static str; 2] = ["tagdir", "config"];
+    let valid_singlet_opts: [(&'static str, Option<char>); 3] =
+        [("dry-run", None), ("diff", None), ("show-config", None)];
+    let valid_valued_opts: [&'static str; 6] =
+        ["tagdir", "config", "scheme", "remap", "function", "variant"];
     let valued_opts = runinfo.values.validate(valid_valued_opts).auto_exit();
-    let _singlet_opts = runinfo.options.validate(valid_singlet_opts).auto_exit();
-    let args = runinfo
-        .arguements
-        .validate_exact([|_: &_| true])
-        .auto_exit();
+    let singlet_opts = runinfo.options.validate(valid_singlet_opts).auto_exit();
+    let any = |_: &_| true;
+    let (command, args) = match runinfo.arguements.first().map(|s| s.as_str()) {
+        Some("check") => (
+            Command::Check,
+            runinfo.arguements.validate_exact([any, any]).auto_exit(),
+        ),
+        Some("watch") => (
+            Command::Watch,
+            runinfo.arguements.validate_exact([any, any]).auto_exit(),
+        ),
+        Some("switch") => {
+            let args = runinfo.arguements.validate_exact([any, any, any]).auto_exit();
+            (Command::Switch(args[1].clone()), args)
+        }
+        //'current' takes no root dir; the last arg is never read.
+        Some("current") => (
+            Command::Current,
+            runinfo.arguements.validate_exact([any]).auto_exit(),
+        ),
+        _ => (
+            Command::Render,
+            runinfo.arguements.validate_exact([any]).auto_exit(),
+        ),
+    };
     let root_dir = PathBuf::from(
-        args.get(0)
-            .expect("No arg[0] found, but passed validation?"),
+        args.last()
+            .expect("No root dir arg found, but passed validation?"),
     );
     let tagdir_path = match valued_opts.get("tagdir") {
         Some(tagdir) => PathBuf::from(tagdir),
@@ -39,9 +92,30 @@ pub fn read_runinfo(runinfo: RunInfo) -> ProgramOptions {
         .filter_map(|path| simple_envpath(path).ok())
         .collect(),
     };
+    let list = |name: &str| -> Vec<String> {
+        match valued_opts.get(name) {
+            Some(list) => list
+                .split(',')
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_owned())
+                .collect(),
+            None => vec![],
+        }
+    };
+    let scheme_override = SchemeOverride {
+        scheme: valued_opts.get("scheme").map(|s| s.to_string()),
+        remaps: list("remap"),
+        functions: list("function"),
+    };
     ProgramOptions {
+        command,
         root_dir,
         tagdir_path,
         config_paths,
+        dry_run: singlet_opts.contains(&"dry-run"),
+        diff: singlet_opts.contains(&"diff"),
+        show_config: singlet_opts.contains(&"show-config"),
+        scheme_override,
+        variant: valued_opts.get("variant").map(|s| s.to_string()),
     }
 }
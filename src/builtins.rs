@@ -0,0 +1,118 @@
+//! Functions that can be used as filters in axbind files or as bind functions without spawning a shell.
+//! Arguments are given after the name, separated by ':'. (e.g. 'lighten:0.1', 'mix:#ffffff:0.25')
+use crate::color::Color;
+
+pub type Builtin = fn(&[&str], &str) -> Result<String, String>;
+
+///Returns the builtin named 'name', if it exists.
+pub fn get(name: &str) -> Option<Builtin> {
+    Some(match name {
+        "upper" => |args, v| no_args(args).map(|_| v.to_uppercase()),
+        "lower" => |args, v| no_args(args).map(|_| v.to_lowercase()),
+        "trim" => |args, v| no_args(args).map(|_| v.trim().to_owned()),
+        "strip_hash" => |args, v| no_args(args).map(|_| v.trim_start_matches('#').to_owned()),
+        "hex" => |args, v| {
+            no_args(args)?;
+            Ok(Color::from_hex(v)?.to_hex())
+        },
+        "hex0x" => |args, v| {
+            no_args(args)?;
+            let c = Color::from_hex(v)?;
+            Ok(format!("0x{:02X}{:02X}{:02X}", c.r, c.g, c.b))
+        },
+        "rgb" => |args, v| {
+            no_args(args)?;
+            let c = Color::from_hex(v)?;
+            Ok(format!("rgb({},{},{})", c.r, c.g, c.b))
+        },
+        "rgba" => |args, v| {
+            let c = Color::from_hex(v)?;
+            let a = match args.first() {
+                Some(_) => fraction_arg(args, 0)?,
+                None => c.a.unwrap_or(255) as f64 / 255.0,
+            };
+            let a = format!("{:.3}", a);
+            let a = a.trim_end_matches('0').trim_end_matches('.');
+            Ok(format!("rgba({},{},{},{})", c.r, c.g, c.b, a))
+        },
+        "hsl" => |args, v| {
+            no_args(args)?;
+            let (h, s, l) = Color::from_hex(v)?.to_hsl();
+            Ok(format!(
+                "hsl({},{}%,{}%)",
+                h.round(),
+                (s * 100.0).round(),
+                (l * 100.0).round()
+            ))
+        },
+        "red" => |args, v| no_args(args).and(Color::from_hex(v)).map(|c| c.r.to_string()),
+        "green" => |args, v| no_args(args).and(Color::from_hex(v)).map(|c| c.g.to_string()),
+        "blue" => |args, v| no_args(args).and(Color::from_hex(v)).map(|c| c.b.to_string()),
+        "alpha" => |args, v| {
+            let c = Color::from_hex(v)?;
+            let a = (fraction_arg(args, 0)? * 255.0).round() as u8;
+            Ok(Color { a: Some(a), ..c }.to_hex())
+        },
+        "lighten" => |args, v| Ok(Color::from_hex(v)?.lighten(fraction_arg(args, 0)?).to_hex()),
+        "darken" => |args, v| Ok(Color::from_hex(v)?.lighten(-fraction_arg(args, 0)?).to_hex()),
+        "mix" => |args, v| {
+            let other = Color::from_hex(args.first().ok_or("'mix' expects a color argument")?)?;
+            let weight = match args.get(1) {
+                Some(_) => fraction_arg(args, 1)?,
+                None => 0.5,
+            };
+            Ok(Color::from_hex(v)?.mix(&other, weight).to_hex())
+        },
+        _ => return None,
+    })
+}
+///Applies the builtin named 'name' to 'value', or 'None' if no such builtin exists.
+pub fn apply(name: &str, args: &[&str], value: &str) -> Option<Result<String, String>> {
+    get(name).map(|builtin| builtin(args, value))
+}
+fn no_args(args: &[&str]) -> Result<(), String> {
+    match args.is_empty() {
+        true => Ok(()),
+        false => Err(format!("unexpected arguments {:?}", args)),
+    }
+}
+///Argument 'i' as a number in 0.0..=1.0.
+fn fraction_arg(args: &[&str], i: usize) -> Result<f64, String> {
+    let raw = args
+        .get(i)
+        .ok_or_else(|| format!("expected a number as argument {}", i + 1))?;
+    match raw.parse::<f64>() {
+        Ok(v) if (0.0..=1.0).contains(&v) => Ok(v),
+        _ => Err(format!("'{}' is not a number between 0 and 1", raw)),
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(name: &str, args: &[&str], value: &str) -> String {
+        apply(name, args, value).unwrap().unwrap()
+    }
+    #[test]
+    fn formats_colors() {
+        assert_eq!(run("rgb", &[], "#282828"), "rgb(40,40,40)");
+        assert_eq!(run("hsl", &[], "#ff0000"), "hsl(0,100%,50%)");
+        assert_eq!(run("lighten", &["0.1"], "#808080"), "#9a9a9a");
+        assert_eq!(run("darken", &["0.1"], "#808080"), "#676767");
+        assert_eq!(run("mix", &["#ffffff"], "#000000"), "#808080");
+        assert_eq!(run("mix", &["#ffffff", "0.25"], "#000000"), "#404040");
+    }
+    #[test]
+    fn rounds_rgba_alpha() {
+        assert_eq!(run("rgba", &[], "#282828"), "rgba(40,40,40,1)");
+        assert_eq!(run("rgba", &[], "#28282880"), "rgba(40,40,40,0.502)");
+        assert_eq!(run("rgba", &["0.25"], "#282828"), "rgba(40,40,40,0.25)");
+        assert_eq!(run("rgba", &["0"], "#282828"), "rgba(40,40,40,0)");
+    }
+    #[test]
+    fn rejects_bad_input() {
+        assert!(apply("rgb", &[], "zz").unwrap().is_err());
+        assert!(apply("lighten", &["x"], "#808080").unwrap().is_err());
+        assert!(apply("nope", &[], "#808080").is_none());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::configs::*;
+use crate::state::{LazyState, StateError};
+use crate::tagfile::{GenerateErr, TagGroup, TagRoot};
+use crate::write::current_mode;
+use crate::{Context, KeyFormat, Path, PathBuf, TableHandle};
+use optwrite::OptWrite;
+
+///A single problem found by 'axbind check'.
+#[derive(Debug)]
+pub enum Problem {
+    Config(ConfigError),
+    TagDir(PathBuf, GenerateErr),
+    AxbindFileMissing(Context, PathBuf),
+    State(Context, StateError),
+    ///(declared, actual)
+    ModeMismatch(Context, PathBuf, u32, u32),
+}
+impl Problem {
+    ///Warnings are reported, but do not fail 'axbind check'.
+    pub fn is_warning(&self) -> bool {
+        matches!(self, Self::ModeMismatch(..))
+    }
+}
+impl From<ConfigError> for Problem {
+    fn from(value: ConfigError) -> Self {
+        Self::Config(value)
+    }
+}
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Config(e) => e.fmt(f),
+            Self::TagDir(path, e) => {
+                writeln!(f, "Unable to interpret tag directory {:?}", path)?;
+                writeln!(f, " - {}", e)
+            }
+            Self::AxbindFileMissing(c, path) => {
+                writeln!(f, "axbind file {:?} does not exist", path)?;
+                writeln!(f, " > expected from '{}'", c)
+            }
+            Self::State(c, e) => e.fmt(f).and_then(|_| writeln!(f, " > from '{}'", c)),
+            Self::ModeMismatch(c, path, declared, actual) => {
+                writeln!(f, "{:?} has mode {:04o}, but {:04o} is declared", path, actual, declared)?;
+                writeln!(f, " > declared from '{}'", c)
+            }
+        }
+    }
+}
+///Forces verification of every scheme in 'registry'.
+pub fn check_schemes(registry: &SchemeRegistry) -> Vec<Problem> {
+    registry
+        .names()
+        .into_iter()
+        .filter_map(|name| registry.get(name).err())
+        .map(Problem::from)
+        .collect()
+}
+///Parses every tag group in the tag directory at 'tag_path' and checks that everything it references exists.
+pub fn check_tagdir(
+    tag_path: &Path,
+    registry: &SchemeRegistry,
+    opt_basis: &Options,
+    meta_opts: &MetaOptions,
+    state: &LazyState,
+) -> Vec<Problem> {
+    let tag_root = match TagRoot::generate_from_dir(tag_path) {
+        Ok(v) => v,
+        Err(e) => return vec![Problem::TagDir(tag_path.to_path_buf(), e)],
+    };
+    let mut problems = Vec::new();
+    for group in tag_root.group_tables() {
+        if let Err(e) = check_taggroup(
+            &tag_root,
+            &group.handle(),
+            registry,
+            opt_basis,
+            meta_opts,
+            state,
+            &mut problems,
+        ) {
+            problems.push(e);
+        }
+    }
+    problems
+}
+fn check_taggroup(
+    tag_root: &TagRoot,
+    handle: &TableHandle,
+    registry: &SchemeRegistry,
+    opt_basis: &Options,
+    meta_opts: &MetaOptions,
+    state: &LazyState,
+    problems: &mut Vec<Problem>,
+) -> Result<(), Problem> {
+    let tag_group = TagGroup::from_table(handle)?;
+    let options = opt_basis.clone().overriden_by(tag_group.options);
+    if let Err(e) = KeyFormat::new(&options, meta_opts) {
+        problems.push(e.into());
+    }
+    let spec = &tag_group.scheme_spec;
+    let scheme_name = state
+        .resolve(spec.scheme)
+        .map_err(|e| Problem::State(handle.context.with("scheme".to_owned()), e))?;
+    match registry.get(scheme_name) {
+        //every scheme that fails verification is already reported by 'check_schemes'.
+        Err(_) => (),
+        Ok(None) => problems.push(
+            ConfigError::SchemeExpected(
+                handle.context.with("scheme".to_owned()),
+                scheme_name.to_owned(),
+            )
+            .into(),
+        ),
+        Ok(Some(scheme)) => {
+            //remaps may only exist in the selected variant.
+            let scheme = match spec.variant {
+                Some(variant) => {
+                    match scheme.with_variant(variant, &handle.context.with("scheme".to_owned())) {
+                        Ok(v) => std::sync::Arc::new(v),
+                        Err(e) => {
+                            problems.push(e.into());
+                            scheme
+                        }
+                    }
+                }
+                None => scheme,
+            };
+            for remap in spec.remaps.iter().filter(|r| !scheme.remaps.contains_key(r.as_str())) {
+                problems.push(scheme.missing_ref(&handle.context, "remaps", remap).into());
+            }
+            for function in spec
+                .functions
+                .iter()
+                .filter(|f| !scheme.functions.contains_key(f.as_str()) && BindFunction::builtin(f).is_none())
+            {
+                problems.push(scheme.missing_ref(&handle.context, "functions", function).into());
+            }
+        }
+    }
+    for file in &tag_group.files {
+        let (file_path, axbind_file_path) = match tag_root.file_paths(file.path, &options, meta_opts) {
+            Ok(paths) => paths,
+            Err(e) => {
+                problems.push(ConfigError::Path(handle.context.with("files".to_owned()), e).into());
+                continue;
+            }
+        };
+        if !axbind_file_path.is_file() {
+            problems.push(Problem::AxbindFileMissing(
+                handle.context.with("files".to_owned()),
+                axbind_file_path,
+            ));
+        }
+        if let Some(declared) = file.mode {
+            match current_mode(&file_path) {
+                Some(actual) if actual != declared => problems.push(Problem::ModeMismatch(
+                    handle.context.with("files".to_owned()),
+                    file_path,
+                    declared,
+                    actual,
+                )),
+                _ => (),
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Colors as understood by builtins and typed scheme bindings.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: Option<u8>,
+}
+impl Color {
+    ///Accepts 'rgb', 'rrggbb' or 'rrggbbaa', with or without a leading '#'.
+    pub fn from_hex(text: &str) -> Result<Color, String> {
+        let digits = text.trim().trim_start_matches('#');
+        let invalid = || format!("'{}' is not a hex color", text);
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let channel = |i: usize, len: usize| {
+            u8::from_str_radix(&digits[i * len..(i + 1) * len], 16).map(|v| match len {
+                1 => v * 17,
+                _ => v,
+            })
+        };
+        let parsed = match digits.len() {
+            3 => (channel(0, 1), channel(1, 1), channel(2, 1), None),
+            6 => (channel(0, 2), channel(1, 2), channel(2, 2), None),
+            8 => (channel(0, 2), channel(1, 2), channel(2, 2), Some(channel(3, 2))),
+            _ => return Err(invalid()),
+        };
+        match parsed {
+            (Ok(r), Ok(g), Ok(b), a) => Ok(Color {
+                r,
+                g,
+                b,
+                a: a.transpose().map_err(|_| invalid())?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+    ///Hue in degrees, saturation and lightness in 0.0..=1.0.
+    pub fn from_hsl(h: f64, s: f64, l: f64) -> Color {
+        let c = (1.0 - (2.0 * l - 1.0).abs()) * s;
+        let hp = h.rem_euclid(360.0) / 60.0;
+        let x = c * (1.0 - (hp % 2.0 - 1.0).abs());
+        let (r, g, b) = match hp as u8 {
+            0 => (c, x, 0.0),
+            1 => (x, c, 0.0),
+            2 => (0.0, c, x),
+            3 => (0.0, x, c),
+            4 => (x, 0.0, c),
+            _ => (c, 0.0, x),
+        };
+        let m = l - c / 2.0;
+        let channel = |v: f64| ((v + m) * 255.0).round().clamp(0.0, 255.0) as u8;
+        Color {
+            r: channel(r),
+            g: channel(g),
+            b: channel(b),
+            a: None,
+        }
+    }
+    ///(hue in degrees, saturation, lightness)
+    pub fn to_hsl(&self) -> (f64, f64, f64) {
+        let (r, g, b) = (
+            self.r as f64 / 255.0,
+            self.g as f64 / 255.0,
+            self.b as f64 / 255.0,
+        );
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let l = (max + min) / 2.0;
+        let d = max - min;
+        if d == 0.0 {
+            return (0.0, 0.0, l);
+        }
+        let s = d / (1.0 - (2.0 * l - 1.0).abs());
+        let h = match max {
+            m if m == r => 60.0 * ((g - b) / d).rem_euclid(6.0),
+            m if m == g => 60.0 * ((b - r) / d + 2.0),
+            _ => 60.0 * ((r - g) / d + 4.0),
+        };
+        (h, s, l)
+    }
+    ///Adds 'amount' (-1.0..=1.0) to this color's lightness.
+    pub fn lighten(&self, amount: f64) -> Color {
+        let (h, s, l) = self.to_hsl();
+        Color {
+            a: self.a,
+            ..Color::from_hsl(h, s, (l + amount).clamp(0.0, 1.0))
+        }
+    }
+    ///'weight' (0.0..=1.0) is how much of 'other' ends up in the result.
+    pub fn mix(&self, other: &Color, weight: f64) -> Color {
+        let channel = |a: u8, b: u8| (a as f64 * (1.0 - weight) + b as f64 * weight).round() as u8;
+        Color {
+            r: channel(self.r, other.r),
+            g: channel(self.g, other.g),
+            b: channel(self.b, other.b),
+            a: match (self.a, other.a) {
+                (None, None) => None,
+                (a, b) => Some(channel(a.unwrap_or(255), b.unwrap_or(255))),
+            },
+        }
+    }
+    ///'#rrggbb', or '#rrggbbaa' if this color has an alpha channel.
+    pub fn to_hex(&self) -> String {
+        match self.a {
+            Some(a) => format!("#{:02x}{:02x}{:02x}{:02x}", self.r, self.g, self.b, a),
+            None => format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Color {
+        Color::from_hex(text).unwrap()
+    }
+    #[test]
+    fn parses_hex() {
+        assert_eq!(hex("#abc"), Color { r: 0xaa, g: 0xbb, b: 0xcc, a: None });
+        assert_eq!(hex("282828"), Color { r: 40, g: 40, b: 40, a: None });
+        assert_eq!(hex("#28282880"), Color { r: 40, g: 40, b: 40, a: Some(0x80) });
+        assert_eq!(hex("#ABCDEF").to_hex(), "#abcdef");
+        for invalid in ["#ab", "#abcd", "#gggggg", "#12345"] {
+            assert!(Color::from_hex(invalid).is_err());
+        }
+    }
+    #[test]
+    fn hsl_round_trips() {
+        assert_eq!(hex("#ff0000").to_hsl(), (0.0, 1.0, 0.5));
+        assert_eq!(Color::from_hsl(120.0, 1.0, 0.5), hex("#00ff00"));
+        assert_eq!(Color::from_hsl(240.0, 1.0, 0.25), hex("#000080"));
+        for text in ["#3366cc", "#282828", "#fabd2f", "#b16286"] {
+            let (h, s, l) = hex(text).to_hsl();
+            assert_eq!(Color::from_hsl(h, s, l), hex(text));
+        }
+    }
+    #[test]
+    fn lightens_and_darkens() {
+        assert_eq!(hex("#808080").lighten(0.1), hex("#9a9a9a"));
+        assert_eq!(hex("#808080").lighten(-0.1), hex("#676767"));
+        assert_eq!(hex("#3366cc").lighten(1.0), hex("#ffffff"));
+        assert_eq!(hex("#3366cc").lighten(-1.0), hex("#000000"));
+        //alpha is kept.
+        assert_eq!(hex("#80808040").lighten(0.1).a, Some(0x40));
+    }
+    #[test]
+    fn mixes() {
+        assert_eq!(hex("#000000").mix(&hex("#ffffff"), 0.5), hex("#808080"));
+        assert_eq!(hex("#ff0000").mix(&hex("#0000ff"), 0.5), hex("#800080"));
+        assert_eq!(hex("#3366cc").mix(&hex("#ffffff"), 0.0), hex("#3366cc"));
+        assert_eq!(hex("#00000000").mix(&hex("#ffffff"), 0.5).a, Some(0x80));
+    }
+}
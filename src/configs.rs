@@ -1,8 +1,11 @@
+use crate::color::Color;
+use crate::paths::{resolve_path, PathError};
 use crate::{
-    escaped_manip, extract_array_strings, extract_char, extract_char_optional,
-    Mapping, Path, RefMapping,
+    builtins, escaped_manip, extract_array_strings, extract_char_optional,
+    Mapping, Path, PathBuf,
 };
 use optwrite::OptWrite;
+use std::sync::{Arc, OnceLock};
 use toml_context::*;
 //rather silly error handling for a rather silly program.
 #[derive(Debug)]
@@ -11,12 +14,16 @@ pub enum ConfigError {
     Misc(String),
     TableRefExpect(Context, TableGetError),
     SchemeExpected(Context, String),
+    ReferenceCycle(Vec<Context>),
+    IncludeCycle(Vec<String>),
+    Path(Context, PathError),
 }
 impl From<TableGetError> for ConfigError {
     fn from(value: TableGetError) -> Self {
         Self::TableGet(value)
     }
 }
+impl std::error::Error for ConfigError {}
 impl std::fmt::Display for ConfigError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use ConfigError::*;
@@ -28,42 +35,308 @@ impl std::fmt::Display for ConfigError {
             Misc(msg) => writeln!(f, "{}", msg),
             SchemeExpected(c, s) => writeln!(f, "No scheme named '{}' exists", s)
                 .and_then(|_| writeln!(f, " > expected from '{}'", c)),
+            ReferenceCycle(chain) => {
+                writeln!(f, "Binding references form a cycle:")?;
+                chain.iter().try_for_each(|c| writeln!(f, " > '{}'", c))
+            }
+            IncludeCycle(chain) => {
+                writeln!(f, "Schemes include/extend each other in a cycle:")?;
+                writeln!(f, " > {}", chain.join(" -> "))
+            }
+            Path(c, e) => e.fmt(f).and_then(|_| writeln!(f, " > from '{}'", c)),
+        }
+    }
+}
+///One config file's worth of (possibly partial) settings.
+#[derive(Debug)]
+pub struct ConfigLayer<'t> {
+    pub source: String,
+    pub scheme_dirs: Vec<PathBuf>,
+    ///Whether 'scheme_dirs' replaces the ones collected so far instead of adding to them.
+    pub replaces_scheme_dirs: bool,
+    pub meta_options: MetaOptions<'t>,
+    pub options: Options<'t>,
+}
+impl ConfigLayer<'_> {
+    ///'path' is the file 'handle' was read from.
+    pub fn from_table<'t>(
+        handle: &TableHandle<'t>,
+        path: &Path,
+    ) -> Result<ConfigLayer<'t>, ConfigError> {
+        let config_dir = path.parent().unwrap_or(Path::new(""));
+        //'scheme_dir' is shorthand for a single 'scheme_dirs' entry.
+        let scheme_dirs = match extract_array_strings(handle.get("scheme_dirs")).optional()? {
+            Some(dirs) => dirs,
+            None => extract_value!(String, handle.get("scheme_dir"))
+                .optional()?
+                .into_iter()
+                .collect(),
+        };
+        Ok(ConfigLayer {
+            source: path.to_string_lossy().into_owned(),
+            scheme_dirs: scheme_dirs
+                .into_iter()
+                .map(|dir| {
+                    resolve_path(dir, config_dir).map_err(|e| {
+                        ConfigError::Path(handle.context.with("scheme_dirs".to_owned()), e)
+                    })
+                })
+                .collect::<Result<Vec<PathBuf>, ConfigError>>()?,
+            replaces_scheme_dirs: false,
+            meta_options: match extract_value!(Table, handle.get("metaoptions")).optional()? {
+                Some(table) => MetaOptions::from_table(table)?,
+                None => MetaOptions::default(),
+            },
+            options: Options::from_optional_table(
+                extract_value!(Table, handle.get("options")).optional()?,
+            )?,
+        })
+    }
+}
+///Settings taken from 'AXBIND_*' environment variables; layered above every config file.
+#[derive(Debug, Default)]
+pub struct EnvOverrides {
+    pub key_format: Option<String>,
+    pub escape_char: Option<String>,
+    pub axbind_file_format: Option<String>,
+    pub scheme_dir: Option<String>,
+}
+impl EnvOverrides {
+    pub fn from_env() -> EnvOverrides {
+        let var = |name: &str| std::env::var(name).ok();
+        EnvOverrides {
+            key_format: var("AXBIND_KEY_FORMAT"),
+            escape_char: var("AXBIND_ESCAPE_CHAR"),
+            axbind_file_format: var("AXBIND_AXBIND_FILE_FORMAT"),
+            scheme_dir: var("AXBIND_SCHEME_DIR"),
+        }
+    }
+    ///One layer per set variable (so each reports its own source).
+    ///'AXBIND_SCHEME_DIR' replaces every configured scheme dir, resolved against the working directory.
+    pub fn layers(&self) -> Result<Vec<ConfigLayer<'_>>, ConfigError> {
+        let layer = |var: &str| ConfigLayer {
+            source: format!("env:{}", var),
+            scheme_dirs: Vec::new(),
+            replaces_scheme_dirs: false,
+            meta_options: MetaOptions::default(),
+            options: Options::default(),
+        };
+        let mut layers = Vec::new();
+        if let Some(key_format) = &self.key_format {
+            let mut l = layer("AXBIND_KEY_FORMAT");
+            l.options.key_format = Some(key_format);
+            layers.push(l);
         }
+        if let Some(escape_char) = &self.escape_char {
+            let mut chars = escape_char.chars();
+            let mut l = layer("AXBIND_ESCAPE_CHAR");
+            l.options.escape_char = match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => {
+                    return Err(ConfigError::Misc(format!(
+                        "'AXBIND_ESCAPE_CHAR' must be a single character (got '{}')",
+                        escape_char
+                    )))
+                }
+            };
+            layers.push(l);
+        }
+        if let Some(axbind_file_format) = &self.axbind_file_format {
+            let mut l = layer("AXBIND_AXBIND_FILE_FORMAT");
+            l.options.axbind_file_format = Some(axbind_file_format);
+            layers.push(l);
+        }
+        if let Some(scheme_dir) = &self.scheme_dir {
+            let mut l = layer("AXBIND_SCHEME_DIR");
+            l.replaces_scheme_dirs = true;
+            l.scheme_dirs.push(resolve_path(scheme_dir, Path::new("")).map_err(|e| {
+                ConfigError::Misc(format!("{} > from 'env:AXBIND_SCHEME_DIR'", e))
+            })?);
+            layers.push(l);
+        }
+        Ok(layers)
     }
 }
 #[derive(Debug)]
 pub struct MasterConfig<'t> {
-    pub scheme_dir: &'t String,
+    ///In order of increasing precedence.
+    pub scheme_dirs: Vec<PathBuf>,
     pub meta_options: MetaOptions<'t>,
     pub options: Options<'t>,
+    ///Where each setting (e.g. 'options.key_format') came from.
+    pub sources: Mapping<String>,
 }
-impl MasterConfig<'_> {
-    pub fn from_table<'t>(handle: &TableHandle<'t>) -> Result<MasterConfig<'t>, ConfigError> {
+impl<'t> MasterConfig<'t> {
+    ///Merges 'layers' (in order of increasing precedence); each layer overrides individual settings of the ones before it.
+    ///'scheme_dirs' are not overridden, but collected from every layer in order (unless a layer replaces them).
+    pub fn from_layers(layers: Vec<ConfigLayer<'t>>) -> Result<MasterConfig<'t>, ConfigError> {
+        let mut sources = Mapping::<String>::new();
+        let mut scheme_dirs = Vec::new();
+        let mut meta_options = MetaOptions::default();
+        let mut options = Options::default();
+        for layer in layers {
+            macro_rules! track {
+                ($section:ident, $name:literal, $($field:ident),*) => {
+                    $(if layer.$section.$field.is_some() {
+                        sources.insert(
+                            format!("{}.{}", $name, stringify!($field)),
+                            layer.source.clone(),
+                        );
+                    })*
+                };
+            }
+            track!(meta_options, "metaoptions", internal_escape_char, wildcard_char, default_delimiter);
+            track!(
+                options,
+                "options",
+                key_format,
+                escape_char,
+                axbind_file_format,
+                strict,
+                atomic_write,
+                preserve_mode,
+                preserve_owner,
+                follow_symlinks
+            );
+            if layer.replaces_scheme_dirs {
+                scheme_dirs.clear();
+                sources.insert("scheme_dirs".to_owned(), layer.source.clone());
+            } else if !layer.scheme_dirs.is_empty() {
+                sources
+                    .entry("scheme_dirs".to_owned())
+                    .and_modify(|s| *s = format!("{}, {}", s, layer.source))
+                    .or_insert(layer.source.clone());
+            }
+            scheme_dirs.extend(layer.scheme_dirs);
+            meta_options = meta_options.overriden_by(layer.meta_options);
+            options = options.overriden_by(layer.options);
+        }
+        macro_rules! default {
+            ($section:ident, $name:literal, $field:ident, $value:expr) => {
+                if $section.$field.is_none() {
+                    $section.$field = Some($value);
+                    sources.insert(format!("{}.{}", $name, stringify!($field)), "default".to_owned());
+                }
+            };
+        }
+        default!(options, "options", strict, false);
+        default!(options, "options", atomic_write, true);
+        default!(options, "options", preserve_mode, true);
+        default!(options, "options", preserve_owner, true);
+        default!(options, "options", follow_symlinks, true);
+        default!(meta_options, "metaoptions", default_delimiter, ":-");
+        macro_rules! require {
+            ($section:ident, $name:literal, $($field:ident),*) => {
+                $(if $section.$field.is_none() {
+                    return Err(ConfigError::Misc(format!(
+                        "No config file sets '{}.{}'",
+                        $name,
+                        stringify!($field)
+                    )));
+                })*
+            };
+        }
+        require!(meta_options, "metaoptions", internal_escape_char, wildcard_char);
+        require!(options, "options", key_format, escape_char, axbind_file_format);
+        if scheme_dirs.is_empty() {
+            return Err(ConfigError::Misc(
+                "No config file sets 'scheme_dirs' (or 'scheme_dir')".to_owned(),
+            ));
+        }
         Ok(MasterConfig {
-            scheme_dir: extract_value!(String, handle.get("scheme_dir"))?,
-            meta_options: MetaOptions::from_table_forced(extract_value!(
-                Table,
-                handle.get("metaoptions")
-            )?)?,
-            options: Options::from_table_forced(&extract_value!(Table, handle.get("options"))?)?,
+            scheme_dirs,
+            meta_options,
+            options,
+            sources,
         })
     }
+    ///The effective settings and where each came from, one per line.
+    pub fn show(&self) -> String {
+        let mut o = String::new();
+        let source = |key: &str| self.sources.get(key).map_or("?", |s| s.as_str()).to_owned();
+        o.push_str(&format!(
+            "scheme_dirs = {:?}  # {}\n",
+            self.scheme_dirs,
+            source("scheme_dirs")
+        ));
+        macro_rules! show {
+            ($section:ident, $name:literal, $($field:ident),*) => {
+                $({
+                    let key = format!("{}.{}", $name, stringify!($field));
+                    o.push_str(&format!(
+                        "{} = {:?}  # {}\n",
+                        key,
+                        self.$section.$field.unwrap(),
+                        source(&key)
+                    ));
+                })*
+            };
+        }
+        show!(meta_options, "metaoptions", internal_escape_char, wildcard_char, default_delimiter);
+        show!(
+            options,
+            "options",
+            key_format,
+            escape_char,
+            axbind_file_format,
+            strict,
+            atomic_write,
+            preserve_mode,
+            preserve_owner,
+            follow_symlinks
+        );
+        o
+    }
 }
-#[derive(Debug)]
-pub struct BindFunction<'t> {
-    shell: &'t String,
-    rcommand: &'t String,
+#[derive(Debug, Clone)]
+pub enum BindFunction {
+    Shell {
+        shell: String,
+        rcommand: String,
+    },
+    ///See 'builtins'.
+    Builtin {
+        name: String,
+        args: Vec<String>,
+        builtin: builtins::Builtin,
+    },
 }
-impl BindFunction<'_> {
+impl BindFunction {
+    ///Parses 'spec' as 'NAME[:ARG...]'; 'None' if no builtin named NAME exists.
+    pub fn builtin(spec: &str) -> Option<BindFunction> {
+        let mut parts = spec.split(':');
+        let name = parts.next().unwrap_or_default();
+        Some(BindFunction::Builtin {
+            builtin: builtins::get(name)?,
+            name: name.to_owned(),
+            args: parts.map(|a| a.to_owned()).collect(),
+        })
+    }
     pub fn apply(&self, key: &str, metaopts: &MetaOptions) -> std::io::Result<String> {
         use std::process::Command;
+        let (shell, rcommand) = match self {
+            Self::Shell { shell, rcommand } => (shell, rcommand),
+            Self::Builtin {
+                name,
+                args,
+                builtin,
+            } => {
+                let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+                return builtin(&args, key).map_err(|e| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("builtin '{}': {}", name, e),
+                    )
+                })
+            }
+        };
         let command = escaped_manip(
-            self.rcommand,
+            rcommand,
             metaopts.internal_escape_char.unwrap(),
             |text| text.replace(metaopts.wildcard_char.unwrap(), key),
         );
         Ok(std::str::from_utf8(
-            Command::new(self.shell)
+            Command::new(shell)
                 .arg("-c")
                 .arg(&command)
                 .output()?
@@ -75,32 +348,197 @@ impl BindFunction<'_> {
     }
 }
 #[derive(Debug)]
-pub struct Scheme<'t> {
-    pub bindings: RefMapping<'t, &'t String>,
-    pub remaps: RefMapping<'t, RefMapping<'t, &'t String>>,
-    pub functions: RefMapping<'t, BindFunction<'t>>,
+pub struct Scheme {
+    pub bindings: Mapping<String>,
+    ///'bindings' before '${KEY}' references are resolved; inheriting schemes and variants build on these.
+    pub raw_bindings: Mapping<String>,
+    pub remaps: Mapping<Mapping<String>>,
+    pub functions: Mapping<BindFunction>,
+    pub variants: Mapping<Variant>,
     pub root_context: String,
-    table: toml::Table,
-    verified: bool,
 }
-impl<'st> Scheme<'st> {
-    fn construct_unverified<'t>(table: toml::Table, root_context: String) -> Scheme<'t> {
-        Scheme {
-            table,
-            root_context,
-            verified: false,
-            bindings: RefMapping::new(),
-            remaps: RefMapping::new(),
-            functions: RefMapping::new(),
+///A '[variants.NAME]' overlay on a scheme's bindings and remaps.
+#[derive(Debug, Clone, Default)]
+pub struct Variant {
+    pub bindings: Mapping<String>,
+    pub remaps: Mapping<Mapping<String>>,
+}
+impl Scheme {
+    ///This scheme with the variant 'name' overlaid; references are resolved over the overlaid bindings.
+    pub fn with_variant(&self, name: &str, spec_context: &Context) -> Result<Scheme, ConfigError> {
+        let variant = self
+            .variants
+            .get(name)
+            .ok_or_else(|| self.missing_ref(spec_context, "variants", name))?;
+        let mut raw_bindings = self.raw_bindings.clone();
+        raw_bindings.extend(variant.bindings.clone());
+        let mut bindings = raw_bindings.clone();
+        resolve_references(
+            &mut bindings,
+            &Context::from(self.root_context.clone())
+                .with("variants".to_owned())
+                .with(name.to_owned()),
+        )?;
+        let mut remaps = self.remaps.clone();
+        for (remap_name, remap) in &variant.remaps {
+            remaps
+                .entry(remap_name.clone())
+                .or_default()
+                .extend(remap.clone());
+        }
+        Ok(Scheme {
+            bindings,
+            raw_bindings,
+            remaps,
+            functions: self.functions.clone(),
+            variants: Mapping::new(),
+            root_context: self.root_context.clone(),
+        })
+    }
+    ///Error for a scheme spec referencing a remap/function ('category') that this scheme lacks.
+    pub fn missing_ref(&self, spec_context: &Context, category: &str, key: &str) -> ConfigError {
+        ConfigError::TableRefExpect(
+            spec_context.with(category.to_owned()).with(key.to_owned()),
+            TableGetError {
+                error: TableGetErr::NoKey,
+                context: Context::from(self.root_context.clone())
+                    .with(category.to_owned())
+                    .with(key.to_owned()),
+            },
+        )
+    }
+}
+///A binding value is either a String, or a typed value normalized to a String.
+///Typed values are tables with exactly one of:
+/// - 'color': hex color string
+/// - 'rgb': [r, g, b] or [r, g, b, a], each 0-255
+/// - 'hsl': [hue (degrees), saturation (0-100), lightness (0-100)]
+///
+///Colors are normalized to '#rrggbb' (or '#rrggbbaa').
+///Hex colors are keyed as typed color bindings render ('#rrggbb'), so '#FFF' and '#ffffff' meet.
+pub fn remap_key(value: &str) -> String {
+    match value.starts_with('#') {
+        true => Color::from_hex(value).map(|c| c.to_hex()).unwrap_or_else(|_| value.to_owned()),
+        false => value.to_owned(),
+    }
+}
+pub fn extract_binding(handle: PotentialValueHandle) -> Result<String, ConfigError> {
+    let table = match extract_value!(Table, handle.clone()) {
+        Ok(table) => table,
+        Err(_) => return Ok(extract_value!(String, handle)?.to_owned()),
+    };
+    let invalid = |msg: String| ConfigError::Misc(format!("{} ({})", msg, table.context));
+    if table.table.len() != 1 {
+        return Err(invalid(
+            "typed bindings must have exactly one of 'color', 'rgb' or 'hsl'".to_owned(),
+        ));
+    }
+    let numbers = |key: &str| -> Result<Option<Vec<f64>>, ConfigError> {
+        match extract_value!(Array, table.get(key)).optional()? {
+            None => Ok(None),
+            Some(array) => array
+                .into_iter()
+                .map(|v| match extract_value!(Integer, v.clone()) {
+                    Ok(i) => Ok(*i as f64),
+                    Err(_) => Ok(*extract_value!(Float, v)?),
+                })
+                .collect::<Result<Vec<f64>, ConfigError>>()
+                .map(Some),
+        }
+    };
+    if let Some(hex) = extract_value!(String, table.get("color")).optional()? {
+        return Color::from_hex(hex).map(|c| c.to_hex()).map_err(invalid);
+    }
+    if let Some(rgb) = numbers("rgb")? {
+        let channels = rgb
+            .iter()
+            .map(|v| match (0.0..=255.0).contains(v) && v.fract() == 0.0 {
+                true => Ok(*v as u8),
+                false => Err(invalid(format!("'{}' is not an integer from 0 to 255", v))),
+            })
+            .collect::<Result<Vec<u8>, ConfigError>>()?;
+        return match channels[..] {
+            [r, g, b] => Ok(Color { r, g, b, a: None }.to_hex()),
+            [r, g, b, a] => Ok(Color { r, g, b, a: Some(a) }.to_hex()),
+            _ => Err(invalid("'rgb' must have 3 or 4 components".to_owned())),
+        };
+    }
+    if let Some(hsl) = numbers("hsl")? {
+        return match hsl[..] {
+            [h, s, l] if (0.0..=100.0).contains(&s) && (0.0..=100.0).contains(&l) => {
+                Ok(Color::from_hsl(h, s / 100.0, l / 100.0).to_hex())
+            }
+            _ => Err(invalid(
+                "'hsl' must be [hue, saturation, lightness] with saturation and lightness from 0 to 100"
+                    .to_owned(),
+            )),
+        };
+    }
+    Err(invalid(
+        "typed bindings must have exactly one of 'color', 'rgb' or 'hsl'".to_owned(),
+    ))
+}
+///Replaces every '${KEY}' in the values of 'bindings' with the (resolved) value of KEY.
+///'${...}' that does not name a binding is left as is.
+fn resolve_references(bindings: &mut Mapping<String>, context: &Context) -> Result<(), ConfigError> {
+    let mut resolved = Mapping::<String>::with_capacity(bindings.len());
+    for key in bindings.keys() {
+        resolve_reference(key, bindings, &mut resolved, &mut Vec::new(), context)?;
+    }
+    for (key, value) in bindings.iter_mut() {
+        if let Some(v) = resolved.remove(key.as_str()) {
+            *value = v;
+        }
+    }
+    Ok(())
+}
+fn resolve_reference(
+    key: &str,
+    raw: &Mapping<String>,
+    resolved: &mut Mapping<String>,
+    chain: &mut Vec<String>,
+    context: &Context,
+) -> Result<String, ConfigError> {
+    if let Some(value) = resolved.get(key) {
+        return Ok(value.clone());
+    }
+    if let Some(i) = chain.iter().position(|k| k == key) {
+        return Err(ConfigError::ReferenceCycle(
+            chain[i..]
+                .iter()
+                .chain(std::iter::once(&key.to_owned()))
+                .map(|k| context.with(k.to_owned()))
+                .collect(),
+        ));
+    }
+    chain.push(key.to_owned());
+    let mut o = String::new();
+    let mut rest = raw[key].as_str();
+    while let Some(start) = rest.find("${") {
+        let after = &rest[start + 2..];
+        match after.find('}').map(|end| &after[..end]) {
+            Some(name) if raw.contains_key(name) => {
+                o.push_str(&rest[..start]);
+                o.push_str(&resolve_reference(name, raw, resolved, chain, context)?);
+                rest = &after[name.len() + 1..];
+            }
+            _ => {
+                o.push_str(&rest[..start + 2]);
+                rest = after;
+            }
         }
     }
+    o.push_str(rest);
+    chain.pop();
+    resolved.insert(key.to_owned(), o.clone());
+    Ok(o)
 }
-#[derive(OptWrite, Debug)]
+#[derive(Default, OptWrite, Debug)]
 pub struct MetaOptions<'t> {
     pub internal_escape_char: Option<char>,
     pub wildcard_char: Option<char>,
-    //temporary until non-primitive data type field is added.
-    _p: core::marker::PhantomData<&'t toml::Table>,
+    ///Separates a key from its default value in axbind files. (e.g. '@{accent:-#ff0000}')
+    pub default_delimiter: Option<&'t str>,
 }
 impl MetaOptions<'_> {
     //perhaps make from_table a derivable trait
@@ -108,15 +546,9 @@ impl MetaOptions<'_> {
         Ok(MetaOptions {
             internal_escape_char: extract_char_optional(table.get("internal_escape_char"))?,
             wildcard_char: extract_char_optional(table.get("wildcard_char"))?,
-            _p: std::marker::PhantomData,
-        })
-    }
-    //cheugy solution (from_table_forced should also be derivable)
-    pub fn from_table_forced<'t>(table: TableHandle<'t>) -> Result<MetaOptions<'t>, ConfigError> {
-        Ok(MetaOptions {
-            internal_escape_char: Some(extract_char(table.get("internal_escape_char"))?),
-            wildcard_char: Some(extract_char(table.get("wildcard_char"))?),
-            _p: std::marker::PhantomData,
+            default_delimiter: extract_value!(String, table.get("default_delimiter"))
+                .optional()?
+                .map(|s| s.as_str()),
         })
     }
 }
@@ -125,6 +557,16 @@ pub struct Options<'t> {
     pub key_format: Option<&'t String>,
     pub escape_char: Option<char>,
     pub axbind_file_format: Option<&'t String>,
+    ///Refuse to write files that still contain unbound 'key_format' occurrences.
+    pub strict: Option<bool>,
+    ///Write through a temporary file in the same directory, then rename it over the target.
+    pub atomic_write: Option<bool>,
+    ///Give a replaced target its previous permissions.
+    pub preserve_mode: Option<bool>,
+    ///Give a replaced target its previous owner and group (unix only).
+    pub preserve_owner: Option<bool>,
+    ///Update the file a symlinked target points to, rather than replacing the link.
+    pub follow_symlinks: Option<bool>,
 }
 impl Options<'_> {
     pub fn from_table<'t>(table: TableHandle<'t>) -> Result<Options<'t>, ConfigError> {
@@ -133,6 +575,19 @@ impl Options<'_> {
             escape_char: extract_char_optional(table.get("escape_char"))?,
             axbind_file_format: extract_value!(String, table.get("axbind_file_format"))
                 .optional()?,
+            strict: extract_value!(Boolean, table.get("strict")).optional()?.copied(),
+            atomic_write: extract_value!(Boolean, table.get("atomic_write"))
+                .optional()?
+                .copied(),
+            preserve_mode: extract_value!(Boolean, table.get("preserve_mode"))
+                .optional()?
+                .copied(),
+            preserve_owner: extract_value!(Boolean, table.get("preserve_owner"))
+                .optional()?
+                .copied(),
+            follow_symlinks: extract_value!(Boolean, table.get("follow_symlinks"))
+                .optional()?
+                .copied(),
         })
     }
     //silly function
@@ -144,35 +599,79 @@ impl Options<'_> {
             None => Ok(Options::default()),
         }
     }
-    pub fn from_table_forced<'t>(table: &TableHandle<'t>) -> Result<Options<'t>, ConfigError> {
-        Ok(Options {
-            key_format: Some(extract_value!(String, table.get("key_format"))?),
-            escape_char: Some(extract_char(table.get("escape_char"))?),
-            axbind_file_format: Some(extract_value!(String, table.get("axbind_file_format"))?),
-        })
+}
+#[derive(Debug)]
+struct SchemeEntry {
+    file: PathBuf,
+    table: toml::Table,
+    root_context: String,
+    verified: OnceLock<Arc<Scheme>>,
+}
+///Names of the schemes that a scheme table 'extends' or '@INCLUDE's (anywhere).
+fn direct_dependencies(table: &toml::Table) -> Vec<&str> {
+    let mut dependencies = Vec::new();
+    if let Some(toml::Value::String(parent)) = table.get("extends") {
+        dependencies.push(parent.as_str());
+    }
+    let mut tables = vec![table];
+    while let Some(table) = tables.pop() {
+        for (k, v) in table {
+            match v {
+                toml::Value::Array(inclusions) if k == "@INCLUDE" => {
+                    dependencies.extend(
+                        inclusions
+                            .iter()
+                            .filter_map(|i| i.as_str())
+                            .map(|i| i.split_once('.').map_or(i, |(scheme, _)| scheme)),
+                    );
+                }
+                toml::Value::Table(t) => tables.push(t),
+                _ => (),
+            }
+        }
     }
+    dependencies
 }
+///Schemes are verified lazily (on first 'get') and shared afterwards.
+///Safe to share between threads.
 #[derive(Debug)]
-pub struct SchemeRegistry<'t> {
-    //rust warns that 'schemes' is unread becuase it is only read through raw pointers via 'lookup'
-    #[allow(unused)]
-    ///Must not grow after load_dir is called
-    schemes: Vec<Scheme<'t>>,
-    lookup: Mapping<*mut Scheme<'t>>,
+pub struct SchemeRegistry {
+    entries: Mapping<SchemeEntry>,
 }
-impl<'st> SchemeRegistry<'st> {
-    //TODO: use TableRoot for implementation.
+impl SchemeRegistry {
     pub fn load_dir(dir: &Path) -> Result<SchemeRegistry, std::io::Error> {
-        use gfunc::fnav;
+        let mut registry = SchemeRegistry {
+            entries: Mapping::new(),
+        };
+        registry.load_into(dir)?;
+        Ok(registry)
+    }
+    ///Loads every directory in 'dirs', in order.
+    ///A scheme defined in more than one directory resolves to the one from the directory latest in 'dirs'.
+    ///Unreadable directories are skipped with a warning.
+    pub fn load_dirs<P: AsRef<Path>>(dirs: &[P]) -> SchemeRegistry {
+        let mut registry = SchemeRegistry {
+            entries: Mapping::new(),
+        };
+        for dir in dirs {
+            if let Err(e) = registry.load_into(dir.as_ref()) {
+                eprintln!(
+                    "[Warn] Unable to read scheme directory {:?}, directory skipped.",
+                    dir.as_ref()
+                );
+                eprintln!(" - {}", e);
+            }
+        }
+        registry
+    }
+    //TODO: use TableRoot for implementation.
+    fn load_into(&mut self, dir: &Path) -> Result<(), std::io::Error> {
         use std::fs;
         use toml::Table;
-        let files = fnav::rsearch_dir_pred(dir, |p| {
-            p.extension().map(|os| os.to_str()) == Some(Some("toml"))
-        })?;
-        let mut schemes = Vec::<Scheme>::with_capacity(files.len());
-        let mut lookup = Mapping::<*mut Scheme>::with_capacity(files.len());
-        for file in &files {
-            let content = match fs::read_to_string(file) {
+        let files = Self::scheme_files(dir)?;
+        self.entries.reserve(files.len());
+        for file in files {
+            let content = match fs::read_to_string(&file) {
                 Ok(content) => content,
                 Err(e) => {
                     eprintln!(
@@ -212,79 +711,195 @@ impl<'st> SchemeRegistry<'st> {
                 }
             };
             let keyname = name.to_string();
-            let context_string: String = dir.join(&keyname).to_string_lossy().into();
-            schemes.push(Scheme::construct_unverified(table, context_string));
-            lookup.insert(keyname, schemes.last_mut().unwrap() as *mut Scheme);
-        }
-        Ok(SchemeRegistry { schemes, lookup })
-    }
-    ///self.schemes MUST not grow.
-    pub fn get<'s>(&'s self, name: &str) -> Result<Option<&'st Scheme>, ConfigError>
-    where
-        's: 'st,
-    {
-        unsafe {
-            match self.lookup.get(name) {
-                Some(ptr) => match self.verify_scheme(&mut **ptr) {
-                    Ok(_) => Ok(Some(&**ptr)),
-                    Err(e) => Err(e),
+            let root_context: String = dir.join(&keyname).to_string_lossy().into();
+            if let Some(overridden) = self.entries.get(&keyname) {
+                eprintln!(
+                    "[Info] Scheme '{}' from {:?} overrides the one from {:?}.",
+                    keyname, file, overridden.file
+                );
+            }
+            self.entries.insert(
+                keyname,
+                SchemeEntry {
+                    file,
+                    table,
+                    root_context,
+                    verified: OnceLock::new(),
                 },
-                None => Ok(None),
+            );
+        }
+        Ok(())
+    }
+    pub fn names(&self) -> Vec<&String> {
+        self.entries.keys().collect()
+    }
+    ///Every file in 'dir' (recursively) that could define a scheme.
+    pub fn scheme_files(dir: &Path) -> Result<Vec<PathBuf>, std::io::Error> {
+        gfunc::fnav::rsearch_dir_pred(dir, |p| {
+            p.extension().map(|os| os.to_str()) == Some(Some("toml"))
+        })
+    }
+    ///Names of the schemes defined in any of 'files'.
+    pub fn names_in(&self, files: &[PathBuf]) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| files.contains(&entry.file))
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+    ///'names' plus every scheme that (transitively) extends or '@INCLUDE's one of them.
+    pub fn with_dependents(&self, mut names: Vec<String>) -> Vec<String> {
+        loop {
+            let dependents: Vec<String> = self
+                .entries
+                .iter()
+                .filter(|(name, _)| !names.contains(name))
+                .filter(|(_, entry)| {
+                    direct_dependencies(&entry.table)
+                        .iter()
+                        .any(|dep| names.iter().any(|n| n == dep))
+                })
+                .map(|(name, _)| name.clone())
+                .collect();
+            if dependents.is_empty() {
+                return names;
             }
+            names.extend(dependents);
         }
     }
-    fn verify_scheme<'s>(&'s self, scheme: &'st mut Scheme<'st>) -> Result<(), ConfigError>
-    where
-        's: 'st,
-    {
-        if scheme.verified {
-            return Ok(());
+    ///Verifies the scheme 'name' if it has not been already.
+    pub fn get(&self, name: &str) -> Result<Option<Arc<Scheme>>, ConfigError> {
+        self.get_chained(name, &mut Vec::new())
+    }
+    ///'chain' is the names of the schemes currently being verified (outermost first),
+    ///each of which includes or extends the next.
+    fn get_chained(
+        &self,
+        name: &str,
+        chain: &mut Vec<String>,
+    ) -> Result<Option<Arc<Scheme>>, ConfigError> {
+        let Some(entry) = self.entries.get(name) else {
+            return Ok(None);
+        };
+        if let Some(scheme) = entry.verified.get() {
+            return Ok(Some(scheme.clone()));
         }
+        if chain.iter().any(|n| n == name) {
+            let mut cycle = chain.clone();
+            cycle.push(name.to_owned());
+            return Err(ConfigError::IncludeCycle(cycle));
+        }
+        chain.push(name.to_owned());
+        let verification = self.verify_scheme(entry, chain);
+        chain.pop();
+        let scheme = Arc::new(verification?);
+        //if another thread got here first, its (identical) result is kept.
+        Ok(Some(entry.verified.get_or_init(|| scheme).clone()))
+    }
+    fn verify_scheme(
+        &self,
+        entry: &SchemeEntry,
+        chain: &mut Vec<String>,
+    ) -> Result<Scheme, ConfigError> {
         let handle = TableHandle {
-            table: &scheme.table,
-            context: scheme.root_context.clone().into(),
+            table: &entry.table,
+            context: entry.root_context.clone().into(),
         };
-        self.populate_bindmap(
-            "bindings",
-            &mut scheme.bindings,
-            extract_value!(Table, handle.get("bindings"))?,
-        )?;
+        let mut scheme = Scheme {
+            bindings: Mapping::new(),
+            raw_bindings: Mapping::new(),
+            remaps: Mapping::new(),
+            functions: Mapping::new(),
+            variants: Mapping::new(),
+            root_context: entry.root_context.clone(),
+        };
+        //inherited entries are overwritten by this scheme's own.
+        let extends = extract_value!(String, handle.get("extends")).optional()?;
+        if let Some(parent_name) = extends {
+            let parent = self.get_chained(parent_name, chain)?.ok_or(ConfigError::SchemeExpected(
+                handle.context.with("extends".to_owned()),
+                parent_name.to_owned(),
+            ))?;
+            scheme.bindings = parent.raw_bindings.clone();
+            scheme.remaps = parent.remaps.clone();
+            scheme.functions = parent.functions.clone();
+            scheme.variants = parent.variants.clone();
+        }
+        let bindings = match extends {
+            Some(_) => extract_value!(Table, handle.get("bindings")).optional()?,
+            None => Some(extract_value!(Table, handle.get("bindings"))?),
+        };
+        if let Some(bindings) = bindings {
+            self.populate_bindmap("bindings", &mut scheme.bindings, bindings, chain)?;
+        }
+        //resolved after overlaying, so overridden bindings reach inherited references.
+        scheme.raw_bindings = scheme.bindings.clone();
+        resolve_references(&mut scheme.bindings, &handle.context.with("bindings".to_owned()))?;
         if let Some(remaps) = extract_value!(Table, handle.get("remaps")).optional()? {
-            for (name, remaptable) in remaps {
-                let mut remap = RefMapping::<&String>::new();
-                self.populate_bindmap("remaps", &mut remap, extract_value!(Table, remaptable)?)?;
-                scheme.remaps.insert(name, remap);
+            self.populate_remaps(&mut scheme.remaps, remaps, chain)?;
+        }
+        if let Some(variants) = extract_value!(Table, handle.get("variants")).optional()? {
+            for (name, varianttable) in variants {
+                let varianttable = extract_value!(Table, varianttable)?;
+                let variant = scheme.variants.entry(name.to_owned()).or_default();
+                if let Some(bindings) =
+                    extract_value!(Table, varianttable.get("bindings")).optional()?
+                {
+                    self.populate_bindmap("bindings", &mut variant.bindings, bindings, chain)?;
+                }
+                if let Some(remaps) = extract_value!(Table, varianttable.get("remaps")).optional()? {
+                    self.populate_remaps(&mut variant.remaps, remaps, chain)?;
+                }
             }
         }
+        //surfaces reference errors (e.g. cycles) introduced by a variant.
+        for name in scheme.variants.keys() {
+            scheme.with_variant(name, &handle.context)?;
+        }
         if let Some(functions) = extract_value!(Table, handle.get("functions")).optional()? {
             for (name, functiontable) in functions {
-                scheme.functions.insert(
-                    name,
-                    BindFunction {
-                        shell: extract_value!(
-                            String,
-                            extract_value!(Table, functiontable.clone())?.get("shell")
-                        )?,
-                        rcommand: extract_value!(
-                            String,
-                            extract_value!(Table, functiontable)?.get("command")
-                        )?,
+                let functiontable = extract_value!(Table, functiontable)?;
+                let function = match extract_value!(String, functiontable.get("builtin"))
+                    .optional()?
+                {
+                    Some(spec) => BindFunction::builtin(spec).ok_or_else(|| {
+                        ConfigError::Misc(format!(
+                            "No builtin function named '{}' exists. ({})",
+                            spec,
+                            functiontable.context.with("builtin".to_owned())
+                        ))
+                    })?,
+                    None => BindFunction::Shell {
+                        shell: extract_value!(String, functiontable.get("shell"))?.to_owned(),
+                        rcommand: extract_value!(String, functiontable.get("command"))?
+                            .to_owned(),
                     },
-                );
+                };
+                scheme.functions.insert(name.to_owned(), function);
             }
         }
+        Ok(scheme)
+    }
+    fn populate_remaps(
+        &self,
+        remaps: &mut Mapping<Mapping<String>>,
+        handle: TableHandle,
+        chain: &mut Vec<String>,
+    ) -> Result<(), ConfigError> {
+        for (name, remaptable) in handle {
+            let remap = remaps.entry(name.to_owned()).or_default();
+            self.populate_bindmap("remaps", remap, extract_value!(Table, remaptable)?, chain)?;
+        }
         Ok(())
     }
     //shared_key is smelly :)
-    fn populate_bindmap<'s>(
-        &'s self,
+    fn populate_bindmap(
+        &self,
         shared_key: &str,
-        map: &mut RefMapping<'st, &'st String>,
-        handle: TableHandle<'st>,
-    ) -> Result<(), ConfigError>
-    where
-        's: 'st,
-    {
+        map: &mut Mapping<String>,
+        handle: TableHandle,
+        chain: &mut Vec<String>,
+    ) -> Result<(), ConfigError> {
         for (k, v) in handle.clone() {
             match k.as_str() {
                 "@INCLUDE" => {
@@ -292,11 +907,8 @@ impl<'st> SchemeRegistry<'st> {
                         let (scheme, path) = inclusion
                             .split_once('.')
                             .unwrap_or((inclusion.as_str(), ""));
-                        let scheme_table = match self.get(scheme)? {
-                            Some(s) => TableHandle {
-                                table: &s.table,
-                                context: s.root_context.clone().into(),
-                            },
+                        let entry = match self.get_chained(scheme, chain)? {
+                            Some(_) => &self.entries[scheme],
                             None => {
                                 return Err(ConfigError::Misc(format!(
                                     "Unrecognized scheme name '{}'. ({})",
@@ -305,6 +917,10 @@ impl<'st> SchemeRegistry<'st> {
                                 )))
                             }
                         };
+                        let scheme_table = TableHandle {
+                            table: &entry.table,
+                            context: entry.root_context.clone().into(),
+                        };
                         let mut nbindmap = extract_value!(Table, scheme_table.get(shared_key))
                             .map_err(|e| {
                                 ConfigError::TableRefExpect(
@@ -320,14 +936,154 @@ impl<'st> SchemeRegistry<'st> {
                                 )
                             })?;
                         }
-                        self.populate_bindmap(shared_key, map, nbindmap)?;
+                        chain.push(scheme.to_owned());
+                        let population = self.populate_bindmap(shared_key, map, nbindmap, chain);
+                        chain.pop();
+                        population?;
                     }
                 }
                 _ => {
-                    map.insert(k, extract_value!(String, v)?);
+                    let key = match shared_key {
+                        "remaps" => remap_key(k),
+                        _ => k.to_owned(),
+                    };
+                    map.insert(key, extract_binding(v)?);
                 }
             }
         }
         Ok(())
     }
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry(schemes: &[&str]) -> SchemeRegistry {
+        let mut entries = Mapping::new();
+        for text in schemes {
+            let table = text.parse::<toml::Table>().unwrap();
+            let name = table["axbind_scheme"].as_str().unwrap().to_owned();
+            entries.insert(
+                name.clone(),
+                SchemeEntry {
+                    file: PathBuf::from(format!("{}.toml", name)),
+                    table,
+                    root_context: name,
+                    verified: OnceLock::new(),
+                },
+            );
+        }
+        SchemeRegistry { entries }
+    }
+    fn include_cycle(registry: &SchemeRegistry, name: &str) -> Vec<String> {
+        match registry.get(name) {
+            Err(ConfigError::IncludeCycle(chain)) => chain,
+            other => panic!("expected an include cycle, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn self_include_is_a_cycle() {
+        let registry = registry(&[r#"
+            axbind_scheme = "a"
+            [bindings]
+            "@INCLUDE" = ["a"]
+            x = "1"
+        "#]);
+        assert_eq!(include_cycle(&registry, "a"), ["a", "a"]);
+    }
+    #[test]
+    fn mutual_include_is_a_cycle() {
+        let registry = registry(&[
+            r#"
+            axbind_scheme = "a"
+            [bindings]
+            "@INCLUDE" = ["b"]
+        "#,
+            r#"
+            axbind_scheme = "b"
+            [bindings]
+            "@INCLUDE" = ["a"]
+        "#,
+        ]);
+        assert_eq!(include_cycle(&registry, "a"), ["a", "b", "a"]);
+        assert_eq!(include_cycle(&registry, "b"), ["b", "a", "b"]);
+    }
+    #[test]
+    fn diamond_include_resolves() {
+        let registry = registry(&[
+            r#"
+            axbind_scheme = "top"
+            [bindings]
+            "@INCLUDE" = ["left", "right"]
+        "#,
+            r#"
+            axbind_scheme = "left"
+            [bindings]
+            "@INCLUDE" = ["base"]
+            left = "l"
+        "#,
+            r#"
+            axbind_scheme = "right"
+            [bindings]
+            "@INCLUDE" = ["base"]
+            right = "r"
+        "#,
+            r#"
+            axbind_scheme = "base"
+            [bindings]
+            base = "b"
+        "#,
+        ]);
+        let top = registry.get("top").unwrap().unwrap();
+        assert_eq!(top.bindings["left"], "l");
+        assert_eq!(top.bindings["right"], "r");
+        assert_eq!(top.bindings["base"], "b");
+    }
+    fn bindings(pairs: &[(&str, &str)]) -> Mapping<String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+    #[test]
+    fn reference_cycle_reports_chain() {
+        let context = Context::from("a".to_owned()).with("bindings".to_owned());
+        let mut refs = bindings(&[("a", "x${b}"), ("b", "${a}y")]);
+        let chain = match resolve_references(&mut refs, &context) {
+            Err(ConfigError::ReferenceCycle(chain)) => chain,
+            other => panic!("expected a reference cycle, got {:?}", other),
+        };
+        let chain: Vec<String> = chain.iter().map(|c| c.to_string()).collect();
+        //which key the cycle is found from depends on iteration order.
+        let expected = |keys: [&str; 3]| -> Vec<String> {
+            keys.iter().map(|k| context.with(k.to_string()).to_string()).collect()
+        };
+        assert!(
+            chain == expected(["a", "b", "a"]) || chain == expected(["b", "a", "b"]),
+            "unexpected chain {:?}",
+            chain
+        );
+    }
+    #[test]
+    fn unknown_reference_is_left_as_is() {
+        let context = Context::from("a".to_owned()).with("bindings".to_owned());
+        let mut refs = bindings(&[("a", "${nope}-${b}"), ("b", "1"), ("c", "${")]);
+        resolve_references(&mut refs, &context).unwrap();
+        assert_eq!(refs["a"], "${nope}-1");
+        assert_eq!(refs["c"], "${");
+    }
+    #[test]
+    fn remap_keys_match_typed_colors() {
+        let registry = registry(&[r##"
+            axbind_scheme = "a"
+            [bindings]
+            bg = { color = "#FFF" }
+            [remaps.dim]
+            "#FFFFFF" = "#eeeeee"
+        "##]);
+        let a = registry.get("a").unwrap().unwrap();
+        assert_eq!(a.bindings["bg"], "#ffffff");
+        assert_eq!(a.remaps["dim"][&a.bindings["bg"]], "#eeeeee");
+    }
+}
@@ -1,19 +1,28 @@
-use aho_corasick::{AhoCorasick};
 use configs::*;
 use std::collections::{HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use toml_context::*;
 pub mod args;
+pub mod builtins;
+pub mod check;
+pub mod color;
 pub mod configs;
+pub mod paths;
+pub mod state;
 pub mod tagfile;
+pub mod watch;
+pub mod write;
 
 pub enum MainError {
     NoConfigFileFound(Vec<PathBuf>),
     InvalidRootDir(PathBuf, std::io::Error),
     SchemeExpected(String, Context),
     FunctionError(Context, String, std::io::Error),
+    HookError(Context, String, std::io::Error),
     ConfigError(configs::ConfigError),
     ReplaceError(Box<dyn std::error::Error>),
+    State(state::StateError),
     Generic(Box<dyn std::fmt::Display>),
 }
 impl From<configs::ConfigError> for MainError {
@@ -21,6 +30,11 @@ impl From<configs::ConfigError> for MainError {
         Self::ConfigError(value)
     }
 }
+impl From<state::StateError> for MainError {
+    fn from(value: state::StateError) -> Self {
+        Self::State(value)
+    }
+}
 impl std::fmt::Display for MainError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         use MainError::*;
@@ -47,88 +61,462 @@ impl std::fmt::Display for MainError {
                 )?;
                 writeln!(f, " - {}", error)
             }
+            HookError(context, command, error) => {
+                writeln!(f, "Error while running hook '{}' ('{}')", context, command)?;
+                writeln!(f, " - {}", error)
+            }
             ReplaceError(e) => e.fmt(f),
+            State(e) => e.fmt(f),
             Generic(e) => e.fmt(f),
             _ => unreachable!(),
         }
     }
 }
+///Runs each of 'commands' with 'sh -c', stopping at the first failure (a non-zero exit counts as one).
+pub fn run_hooks(commands: &[&String], hooks_context: &Context) -> Result<(), MainError> {
+    for (i, command) in commands.iter().enumerate() {
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .status()
+            .and_then(|status| match status.success() {
+                true => Ok(()),
+                false => Err(std::io::Error::other(format!("command exited with {}", status))),
+            })
+            .map_err(|e| {
+                MainError::HookError(hooks_context.with(i.to_string()), (*command).to_owned(), e)
+            })?;
+    }
+    Ok(())
+}
+///What writing rendered contents to a target file would do to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileStatus {
+    Create,
+    Change,
+    Unchanged,
+}
+impl FileStatus {
+    pub fn compare(existing: Option<&str>, contents: &str) -> FileStatus {
+        match existing {
+            None => FileStatus::Create,
+            Some(existing) if existing == contents => FileStatus::Unchanged,
+            Some(_) => FileStatus::Change,
+        }
+    }
+}
+impl std::fmt::Display for FileStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FileStatus::Create => "create",
+            FileStatus::Change => "change",
+            FileStatus::Unchanged => "unchanged",
+        })
+    }
+}
+impl FileStatus {
+    ///Describes the status once the write has happened.
+    pub fn done(&self) -> &'static str {
+        match self {
+            FileStatus::Create => "created",
+            FileStatus::Change => "updated",
+            FileStatus::Unchanged => "unchanged",
+        }
+    }
+}
+///Per-file outcomes, counted over a tag group or a whole run.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RunSummary {
+    pub created: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    ///Files not rendered because of an error.
+    pub skipped: usize,
+    ///Tag groups whose post hooks failed (after their files were written).
+    pub failed_hooks: usize,
+}
+impl RunSummary {
+    pub fn record(&mut self, status: FileStatus) {
+        match status {
+            FileStatus::Create => self.created += 1,
+            FileStatus::Change => self.updated += 1,
+            FileStatus::Unchanged => self.unchanged += 1,
+        }
+    }
+    pub fn merge(&mut self, other: RunSummary) {
+        self.created += other.created;
+        self.updated += other.updated;
+        self.unchanged += other.unchanged;
+        self.skipped += other.skipped;
+        self.failed_hooks += other.failed_hooks;
+    }
+    ///Whether any file was (or, in dry-run/diff mode, would be) created or updated.
+    pub fn differences(&self) -> bool {
+        self.created + self.updated > 0
+    }
+}
+impl std::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} created, {} updated, {} unchanged, {} skipped",
+            self.created, self.updated, self.unchanged, self.skipped
+        )?;
+        if self.failed_hooks > 0 {
+            write!(f, ", {} failed post hooks", self.failed_hooks)?;
+        }
+        Ok(())
+    }
+}
+///Reads the current contents of a target file, or `None` if it does not exist yet.
+pub fn read_existing(path: &Path) -> std::io::Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+///Unified diff (3 lines of context) turning 'existing' into 'contents'.
+pub fn unified_diff(path: &Path, existing: Option<&str>, contents: &str) -> String {
+    let label = path.to_string_lossy();
+    similar::TextDiff::from_lines(existing.unwrap_or(""), contents)
+        .unified_diff()
+        .context_radius(3)
+        .header(&label, &label)
+        .to_string()
+}
 pub type Mapping<T> = HashMap<String, T>;
-pub type RefMapping<'t, T> = HashMap<&'t String, T>;
 
 pub fn axbind_replace<S: AsRef<str>>(
     text: &str,
-    bindings: &RefMapping<S>,
+    bindings: &Mapping<S>,
+    scheme: &Scheme,
     options: &configs::Options,
     meta_opts: &MetaOptions,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let pairs: Vec<(String, &str)> = bindings
-        .into_iter()
-        .map(|(k, v)| {
-            (
-                escaped_manip(
-                    options.key_format.unwrap(),
-                    meta_opts.internal_escape_char.unwrap(),
-                    |s| s.replace(meta_opts.wildcard_char.unwrap(), k.as_str()),
-                ),
-                v.as_ref(),
-            )
-        })
-        .collect();
-    let searcher = AhoCorasick::new(pairs.iter().map(|(k, _)| k.as_str()))?;
-    //weirdchamp collect then slice
-    let replacer = pairs.into_iter().map(|(_, v)| v).collect::<Vec<&str>>();
-    Ok(escaped_manip(text, options.escape_char.unwrap(), |chunk| {
-        searcher.replace_all(chunk, replacer.as_slice())
-    }))
+    let format = KeyFormat::new(options, meta_opts)?;
+    let keys = scan_keys(bindings);
+    let mut o = String::with_capacity(text.len());
+    for (_, chunk, escaped) in unescaped_chunks(text, options.escape_char.unwrap()) {
+        let mut last = 0;
+        for found in format.scan(chunk, &keys) {
+            let mut value = match bindings.get(found.key) {
+                Some(v) => v.as_ref().to_owned(),
+                None => match found.default {
+                    Some(default) => default.to_owned(),
+                    None => continue,
+                },
+            };
+            for filter in &found.filters {
+                value = apply_filter(value, filter, scheme, meta_opts)?;
+            }
+            o.push_str(&chunk[last..found.start]);
+            o.push_str(value.as_str());
+            last = found.end;
+        }
+        o.push_str(&chunk[last..]);
+        o.push_str(escaped);
+    }
+    Ok(o)
 }
-//this entire function may be a codesmell (it definitely is)
-pub fn get_bindings<'t>(
-    registry: &'t SchemeRegistry<'t>,
-    scheme_spec: &tagfile::SchemeSpec,
+#[derive(Debug)]
+pub enum FilterError {
+    Unknown(String),
+    NoRemap(String),
+    Function(String, String, std::io::Error),
+    Builtin(String, String),
+}
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use FilterError::*;
+        match self {
+            Unknown(filter) => writeln!(
+                f,
+                "No scheme function or builtin exists for filter '{}'",
+                filter
+            ),
+            NoRemap(remap) => writeln!(f, "Filter expects remap '{}', which the scheme lacks", remap),
+            Function(function, key, error) => {
+                writeln!(
+                    f,
+                    "Error while applying bind function '{}' on key '{}'",
+                    function, key
+                )?;
+                writeln!(f, " - {}", error)
+            }
+            Builtin(filter, msg) => writeln!(f, "Error while applying filter '{}': {}", filter, msg),
+        }
+    }
+}
+impl std::error::Error for FilterError {}
+///Applies a single filter of an axbind file key (e.g. the 'upper' in '@{bg|upper}') to 'value'.
+///A filter is either 'remap:NAME', the name of a scheme function, or a builtin (see 'builtins').
+pub fn apply_filter(
+    value: String,
+    filter: &str,
+    scheme: &Scheme,
     meta_opts: &MetaOptions,
-    spec_context: Context,
-) -> Result<RefMapping<'t, String>, MainError> {
-    let scheme = registry
+) -> Result<String, FilterError> {
+    let mut parts = filter.split(':');
+    let name = parts.next().unwrap_or_default();
+    let args: Vec<&str> = parts.collect();
+    if name == "remap" {
+        let remap_name = args.first().copied().unwrap_or_default();
+        let remap = scheme
+            .remaps
+            .get(remap_name)
+            .ok_or_else(|| FilterError::NoRemap(remap_name.to_owned()))?;
+        return Ok(remap.get(&remap_key(&value)).cloned().unwrap_or(value));
+    }
+    if let Some(function) = scheme.functions.get(name) {
+        return function
+            .apply(value.as_str(), meta_opts)
+            .map_err(|e| FilterError::Function(name.to_owned(), value, e));
+    }
+    match builtins::apply(name, &args, value.as_str()) {
+        Some(result) => result.map_err(|e| FilterError::Builtin(filter.to_owned(), e)),
+        None => Err(FilterError::Unknown(filter.to_owned())),
+    }
+}
+///'key_format' split around its wildcard character.
+#[derive(Debug)]
+pub struct KeyFormat<'m> {
+    pub prefix: String,
+    pub suffix: String,
+    pub default_delimiter: &'m str,
+}
+///A single occurrence of a key in some text.
+#[derive(Debug)]
+pub struct KeyMatch<'s> {
+    pub start: usize,
+    pub end: usize,
+    pub key: &'s str,
+    pub filters: Vec<&'s str>,
+    pub default: Option<&'s str>,
+}
+impl<'m> KeyFormat<'m> {
+    ///Errors if 'key_format' does not have exactly one (unescaped) wildcard, or 'default_delimiter' is empty.
+    pub fn new(
+        options: &configs::Options,
+        meta_opts: &MetaOptions<'m>,
+    ) -> Result<KeyFormat<'m>, ConfigError> {
+        let default_delimiter = meta_opts.default_delimiter.unwrap();
+        //an empty delimiter would split a default off any key with a bound prefix.
+        if default_delimiter.is_empty() {
+            return Err(ConfigError::Misc(
+                "'metaoptions.default_delimiter' must not be empty".to_owned(),
+            ));
+        }
+        let escape = meta_opts.internal_escape_char.unwrap();
+        let wildcard = meta_opts.wildcard_char.unwrap();
+        let key_format = options.key_format.unwrap();
+        let mut parts = vec![String::new()];
+        let mut chars = key_format.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                c if c == escape => parts.last_mut().unwrap().extend(chars.next()),
+                c if c == wildcard => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+        let suffix = parts.pop().unwrap();
+        match parts.pop() {
+            Some(prefix) if parts.is_empty() => Ok(KeyFormat {
+                prefix,
+                suffix,
+                default_delimiter,
+            }),
+            _ => Err(ConfigError::Misc(format!(
+                "'key_format' must contain the wildcard character '{}' exactly once (got '{}')",
+                wildcard, key_format
+            ))),
+        }
+    }
+    ///Finds every key in 'text'.
+    ///'keys' (the bound keys) are matched exactly, longest first.
+    ///If 'suffix' is not empty, a key may be followed by any number of '|'-separated filters,
+    ///then by 'default_delimiter' and a default value. (e.g. '@{bg|strip_hash|upper:-FFFFFF}')
+    ///If both 'prefix' and 'suffix' are not empty, unbound keys (without whitespace) are found too.
+    pub fn scan<'s>(&self, text: &'s str, keys: &[&str]) -> Vec<KeyMatch<'s>> {
+        let mut o = Vec::new();
+        let mut cursor = 0;
+        while cursor < text.len() {
+            let found = match text[cursor..].starts_with(self.prefix.as_str()) {
+                true => self.match_at(text, cursor, keys),
+                false => None,
+            };
+            match found {
+                Some(found) => {
+                    cursor = found.end;
+                    o.push(found);
+                }
+                None => cursor += text[cursor..].chars().next().map_or(1, char::len_utf8),
+            }
+        }
+        o
+    }
+    ///Matches a key whose prefix starts at 'start'.
+    fn match_at<'s>(&self, text: &'s str, start: usize, keys: &[&str]) -> Option<KeyMatch<'s>> {
+        let inner_start = start + self.prefix.len();
+        let rest = &text[inner_start..];
+        for key in keys.iter().filter(|k| !k.is_empty() && rest.starts_with(*k)) {
+            //without a suffix, nothing delimits filters or a default.
+            let tail = match self.suffix.is_empty() {
+                true => "",
+                false => match rest[key.len()..].find(self.suffix.as_str()) {
+                    Some(tail_len) => &rest[key.len()..key.len() + tail_len],
+                    None => continue,
+                },
+            };
+            if tail.is_empty() || tail.starts_with('|') || tail.starts_with(self.default_delimiter) {
+                return Some(self.key_match(text, start, inner_start, key.len(), tail));
+            }
+        }
+        if self.prefix.is_empty() || self.suffix.is_empty() {
+            return None;
+        }
+        let inner = &rest[..rest.find(self.suffix.as_str())?];
+        let key_len = inner.find('|').unwrap_or(inner.len());
+        let key_len = inner
+            .find(self.default_delimiter)
+            .map_or(key_len, |i| key_len.min(i));
+        let key = &inner[..key_len];
+        match key.is_empty() || key.contains(char::is_whitespace) {
+            true => None,
+            false => Some(self.key_match(text, start, inner_start, key_len, &inner[key_len..])),
+        }
+    }
+    fn key_match<'s>(
+        &self,
+        text: &'s str,
+        start: usize,
+        inner_start: usize,
+        key_len: usize,
+        tail: &'s str,
+    ) -> KeyMatch<'s> {
+        let (piped, default) = match tail.split_once(self.default_delimiter) {
+            Some((piped, default)) => (piped, Some(default)),
+            None => (tail, None),
+        };
+        KeyMatch {
+            start,
+            end: inner_start + key_len + tail.len() + self.suffix.len(),
+            key: &text[inner_start..inner_start + key_len],
+            filters: piped.split('|').skip(1).collect(),
+            default,
+        }
+    }
+}
+///Bound keys in the order 'KeyFormat::scan' tries them (longest first).
+pub fn scan_keys<S>(bindings: &Mapping<S>) -> Vec<&str> {
+    let mut keys: Vec<&str> = bindings.keys().map(|k| k.as_str()).collect();
+    keys.sort_by_key(|k| std::cmp::Reverse(k.len()));
+    keys
+}
+///A 'key_format' occurrence in an axbind file that no binding matched.
+#[derive(Debug)]
+pub struct UnresolvedKey {
+    pub key: String,
+    pub line: usize,
+    pub column: usize,
+}
+///Finds every unescaped key in 'text' that has neither a binding nor a default value.
+///(Unbound keys can only be found if 'key_format' has both a prefix and a suffix.)
+pub fn find_unresolved<S>(
+    text: &str,
+    bindings: &Mapping<S>,
+    options: &configs::Options,
+    meta_opts: &MetaOptions,
+) -> Result<Vec<UnresolvedKey>, ConfigError> {
+    let mut o = Vec::new();
+    let format = KeyFormat::new(options, meta_opts)?;
+    let keys = scan_keys(bindings);
+    for (offset, chunk, _) in unescaped_chunks(text, options.escape_char.unwrap()) {
+        for found in format.scan(chunk, &keys) {
+            if found.default.is_none() && !bindings.contains_key(found.key) {
+                let (line, column) = line_column(text, offset + found.start);
+                o.push(UnresolvedKey {
+                    key: found.key.to_owned(),
+                    line,
+                    column,
+                });
+            }
+        }
+    }
+    Ok(o)
+}
+///The same chunks 'escaped_manip' would manipulate, as (byte offset in 'text', chunk, escaped text after chunk).
+fn unescaped_chunks(text: &str, escape: char) -> Vec<(usize, &str, &str)> {
+    let mut o = Vec::new();
+    let mut start = 0;
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        if c == escape {
+            let escaped_start = i + c.len_utf8();
+            let next_start = chars.next().map_or(text.len(), |(j, e)| j + e.len_utf8());
+            o.push((start, &text[start..i], &text[escaped_start..next_start]));
+            start = next_start;
+        }
+    }
+    o.push((start, &text[start..], ""));
+    o
+}
+///1-based (line, column) of the byte 'offset' in 'text'.
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+pub fn get_scheme(
+    registry: &SchemeRegistry,
+    scheme_spec: &tagfile::SchemeSpec,
+    spec_context: &Context,
+) -> Result<Arc<Scheme>, MainError> {
+    Ok(registry
         .get(scheme_spec.scheme)?
         .ok_or(ConfigError::SchemeExpected(
             spec_context.with("scheme".to_owned()),
             scheme_spec.scheme.to_owned(),
-        ))?;
+        ))?)
+}
+//this entire function may be a codesmell (it definitely is)
+pub fn get_bindings(
+    scheme: &Scheme,
+    scheme_spec: &tagfile::SchemeSpec,
+    meta_opts: &MetaOptions,
+    spec_context: Context,
+) -> Result<Mapping<String>, MainError> {
     macro_rules! gen_error {
         ($category:expr, $key:expr) => {
-            ConfigError::TableRefExpect(
-                spec_context
-                    .with($category.to_owned())
-                    .with(($key).to_owned()),
-                TableGetError {
-                    error: TableGetErr::NoKey,
-                    context: Context::from(scheme.root_context.clone())
-                        .with($category.to_owned())
-                        .with(($key).to_owned()),
-                },
-            )
+            scheme.missing_ref(&spec_context, $category, $key)
         };
     }
     let mut inter_o = scheme.bindings.clone();
     for remap_name in &scheme_spec.remaps {
         let s_remaps = scheme
             .remaps
-            .get(remap_name)
+            .get(remap_name.as_str())
             .ok_or(gen_error!("remaps", *remap_name))?;
         for val in inter_o.values_mut() {
-            if let Some(remap) = s_remaps.get(val) {
-                *val = *remap;
+            if let Some(remap) = s_remaps.get(&remap_key(val)) {
+                *val = remap.clone();
             }
         }
     }
-    let mut o =
-        RefMapping::<String>::from_iter(inter_o.into_iter().map(|(k, v)| (k, v.to_owned())));
+    let mut o = inter_o;
     for function_name in &scheme_spec.functions {
-        let s_function = scheme
-            .functions
-            .get(function_name)
-            .ok_or(gen_error!("functions", *function_name))?;
+        let builtin;
+        let s_function = match scheme.functions.get(function_name.as_str()) {
+            Some(function) => function,
+            None => {
+                builtin = BindFunction::builtin(function_name)
+                    .ok_or(gen_error!("functions", *function_name))?;
+                &builtin
+            }
+        };
         for val in o.values_mut() {
             *val = s_function.apply(val.as_str(), meta_opts).map_err(|e| {
                 MainError::FunctionError(
@@ -189,3 +577,77 @@ where
     o.push_str(manip(current_chunk).as_str());
     o
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matched_keys(key_format: &str, text: &str, keys: &[&str]) -> Vec<String> {
+        let key_format = key_format.to_owned();
+        let options = configs::Options {
+            key_format: Some(&key_format),
+            ..Default::default()
+        };
+        let meta_opts = MetaOptions {
+            internal_escape_char: Some('\\'),
+            wildcard_char: Some('*'),
+            default_delimiter: Some(":-"),
+        };
+        let format = KeyFormat::new(&options, &meta_opts).unwrap();
+        format.scan(text, keys).iter().map(|m| m.key.to_owned()).collect()
+    }
+    #[test]
+    fn empty_suffix_matches_bound_keys() {
+        assert_eq!(
+            matched_keys("@*", "@bg; \"@bg\" @bg, @fgx", &["bg", "fg"]),
+            vec!["bg", "bg", "bg", "fg"]
+        );
+    }
+    #[test]
+    fn empty_prefix_matches_bound_keys() {
+        assert_eq!(matched_keys("*}", "{bg} fg}", &["bg", "fg"]), vec!["bg", "fg"]);
+    }
+    #[test]
+    fn longest_key_wins() {
+        assert_eq!(
+            matched_keys("@{*}", "@{fg_alt} @{fg}", &["fg_alt", "fg"]),
+            vec!["fg_alt", "fg"]
+        );
+    }
+    #[test]
+    fn keys_may_contain_whitespace() {
+        assert_eq!(
+            matched_keys("@{*}", "@{dark bg|upper:-x}", &["dark bg"]),
+            vec!["dark bg"]
+        );
+    }
+    #[test]
+    fn wildcard_must_appear_once() {
+        let meta_opts = MetaOptions {
+            internal_escape_char: Some('\\'),
+            wildcard_char: Some('*'),
+            default_delimiter: Some(":-"),
+        };
+        for key_format in ["@{}", "@{*}*"] {
+            let key_format = key_format.to_owned();
+            let options = configs::Options {
+                key_format: Some(&key_format),
+                ..Default::default()
+            };
+            assert!(KeyFormat::new(&options, &meta_opts).is_err());
+        }
+    }    #[test]
+    fn default_delimiter_must_not_be_empty() {
+        let key_format = "@{*}".to_owned();
+        let options = configs::Options {
+            key_format: Some(&key_format),
+            ..Default::default()
+        };
+        let meta_opts = MetaOptions {
+            internal_escape_char: Some('\\'),
+            wildcard_char: Some('*'),
+            default_delimiter: Some(""),
+        };
+        assert!(KeyFormat::new(&options, &meta_opts).is_err());
+    }
+}
@@ -2,111 +2,209 @@ use axbind::*;
 use gfunc::fnav::{rsearch_dir, MetaType};
 use gfunc::run::RunInfo;
 use optwrite::OptWrite;
-use std::path::{PathBuf};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use toml_context::{TableHandle};
+use std::sync::Arc;
+use toml_context::{Context, TableHandle};
 //parse::<toml::Table>
 
+//this is some bullshit
+macro_rules! warn_continue {
+    ($result:expr, $msg:expr) => {
+        {
+            match $result {
+                Ok(v) => v,
+                Err(e) => {
+                    eprintln!("[Warn] {}", $msg);
+                    eprintln!(" - {}", e);
+                    continue;
+                }
+            }
+        }
+    }
+}
+
 ///deserves to be rewritten tbh
 fn program() -> Result<(), MainError> {
     let program_options = args::read_runinfo(RunInfo::get_from_env());
     eprintln!(" >> PROGRAM OPTIONS :: {:#?}", program_options);
-    let config_root = gfunc::for_until(&program_options.config_paths, |p| {
-        toml_context::TableRoot::from_file_path(p).ok()
-    })
-    .ok_or(MainError::NoConfigFileFound(program_options.config_paths))?;
-    eprintln!(" >> CONFIG FILE :: {}", config_root.context);
-    let master_config = configs::MasterConfig::from_table(&config_root.handle())?;
+    if program_options.command == args::Command::Current {
+        match &state::State::load(&state::State::path()?)?.active {
+            Some(active) => println!("{}", active),
+            None => eprintln!("[Info] No scheme is active"),
+        }
+        return Ok(());
+    }
+    //a file may be listed twice (e.g. with 'XDG_CONFIG_HOME=$HOME/.config'),
+    //only its highest precedence entry is kept.
+    let mut seen_configs = std::collections::HashSet::new();
+    let config_paths: Vec<&PathBuf> = program_options
+        .config_paths
+        .iter()
+        .filter(|p| p.canonicalize().is_ok_and(|canonical| seen_configs.insert(canonical)))
+        .collect();
+    //loaded lowest precedence first ('config_paths' is in order of decreasing precedence).
+    let config_roots: Vec<(PathBuf, toml_context::TableRoot)> = config_paths
+        .into_iter()
+        .rev()
+        .filter_map(|p| match toml_context::TableRoot::from_file_path(p) {
+            Ok(root) => Some((p.clone(), root)),
+            Err(e) => {
+                eprintln!("[Warn] Unable to read config file {:?} (file skipped)", p);
+                eprintln!(" - {}", e);
+                None
+            }
+        })
+        .collect();
+    if config_roots.is_empty() {
+        return Err(MainError::NoConfigFileFound(program_options.config_paths.clone()));
+    }
+    eprintln!(" >> CONFIG FILES :: {:?}", config_roots.iter().map(|(p, _)| p).collect::<Vec<_>>());
+    let env_overrides = configs::EnvOverrides::from_env();
+    let mut layers = config_roots
+        .iter()
+        .map(|(path, root)| configs::ConfigLayer::from_table(&root.handle(), path))
+        .collect::<Result<Vec<configs::ConfigLayer>, configs::ConfigError>>()?;
+    layers.extend(env_overrides.layers()?);
+    let master_config = configs::MasterConfig::from_layers(layers)?;
     eprintln!(" >> CONFIGS :: {:#?}", master_config);
-    //TODO: fix scheme_dir not being relative to configuration directory.
-    //perhaps add a gfunc function for easy relative/absolute parsing
-    let scheme_path = PathBuf::from(String::clone(&config_root.context.branch))
-        .with_file_name(master_config.scheme_dir);
-    eprintln!(" >> FULL SCHEME DIR :: {:?}", scheme_path);
-    let scheme_registry = configs::SchemeRegistry::load_dir(scheme_path.as_path())
-        .map_err(|e| MainError::Generic(Box::new(e)))?;
+    if program_options.show_config {
+        print!("{}", master_config.show());
+        return Ok(());
+    }
+    let scheme_paths = &master_config.scheme_dirs;
+    eprintln!(" >> FULL SCHEME DIRS :: {:?}", scheme_paths);
+    let scheme_registry = configs::SchemeRegistry::load_dirs(scheme_paths);
     let tagdir_paths = rsearch_dir(
         &program_options.root_dir,
         &program_options.tagdir_path,
         MetaType::Directory,
     )
-    .map_err(|e| MainError::InvalidRootDir(program_options.root_dir, e))?;
+    .map_err(|e| MainError::InvalidRootDir(program_options.root_dir.clone(), e))?;
     eprintln!(" >> SCHEME REGISTRY :: {:#?}", scheme_registry);
     eprintln!(" >> TAGDIRS :: {:#?}", tagdir_paths);
-    //this is some bullshit
-    macro_rules! warn_continue {
-        ($result:expr, $msg:expr) => {
-            {
-                match $result {
-                    Ok(v) => v,
-                    Err(e) => {
-                        eprintln!("[Warn] {}", $msg);
-                        eprintln!(" - {}", e);
-                        continue;
-                    }
-                }
+    let mut state = state::LazyState::default();
+    match &program_options.command {
+        args::Command::Check => {
+            return check_all(&tagdir_paths, &scheme_registry, &master_config, &state)
+        }
+        args::Command::Switch(name) => {
+            let state_path = state::State::path()?;
+            let mut switched = state::State::load(&state_path)?;
+            let target = switched.target(name)?.to_owned();
+            if scheme_registry.get(&target)?.is_none() {
+                return Err(MainError::SchemeExpected(target, Context::from("switch".to_owned())));
+            }
+            switched.switch(target);
+            if !(program_options.dry_run || program_options.diff) {
+                switched.save(&state_path)?;
             }
+            eprintln!("[Info] Active scheme is now '{}'", switched.active.as_ref().unwrap());
+            state = switched.into();
         }
+        _ => (),
     }
-    for tag_path in tagdir_paths {
-        let tag_root = warn_continue!(tagfile::TagRoot::generate_from_dir(&tag_path),
+    let mut summary = RunSummary::default();
+    for tag_path in &tagdir_paths {
+        let tag_root = warn_continue!(tagfile::TagRoot::generate_from_dir(tag_path),
             format!("Unable to interpret tag directory {:?}", tag_path));
-        match &tag_root.groups {
-            None => warn_continue!(evaluate_taggroup(
+        for group in tag_root.group_tables() {
+            summary.merge(warn_continue!(evaluate_taggroup(
                 &tag_root,
-                &tag_root.main.handle(),
+                &group.handle(),
                 &master_config.options,
                 &scheme_registry,
                 &master_config.meta_options,
+                &program_options,
+                &state,
             ),
-            format!("Unable to apply group '{}'", tag_root.main.context)),
-            Some(groups) => {
-                for group in groups {
-                    warn_continue!(evaluate_taggroup(
-                        &tag_root,
-                        &group.handle(),
-                        &master_config.options,
-                        &scheme_registry,
-                        &master_config.meta_options,
-                    ),
-                    format!("Unable to apply group '{}'", group.context));
-                }
-            }
+            format!("Unable to apply group '{}'", group.context)));
         }
     }
+    eprintln!("[Info] {}", summary);
+    if program_options.command == args::Command::Watch {
+        return watch(&tagdir_paths, &master_config, scheme_registry, &program_options, state);
+    }
     eprintln!(" >> OK <<");
+    //lets --diff gate scripts/hooks
+    if program_options.diff && summary.differences() {
+        exit(1);
+    }
     Ok(())
 }
+///Applies the run's scheme overrides and resolves the active scheme.
+fn effective_scheme_spec<'a>(
+    scheme_spec: tagfile::SchemeSpec<'a>,
+    program_options: &'a args::ProgramOptions,
+    state: &'a state::LazyState,
+) -> Result<tagfile::SchemeSpec<'a>, MainError> {
+    let mut scheme_spec = scheme_spec.overriden_by(&program_options.scheme_override);
+    scheme_spec.scheme = state.resolve(scheme_spec.scheme)?;
+    Ok(scheme_spec)
+}
 //cannot be bothered with this function signature, might as well be a macro.
-fn evaluate_taggroup<'a>(
+fn evaluate_taggroup(
     tag_root: &tagfile::TagRoot,
     tag_group_handle: &TableHandle,
     opt_basis: &configs::Options,
-    registry: &'a configs::SchemeRegistry<'a>,
+    registry: &configs::SchemeRegistry,
     meta_opts: &configs::MetaOptions,
-) -> Result<(), MainError> {
-    let mut affecting_dir = tag_root.path.clone();
-    affecting_dir.pop();
+    program_options: &args::ProgramOptions,
+    state: &state::LazyState,
+) -> Result<RunSummary, MainError> {
     eprintln!(">> -- EVALUATING TAGGROUP :: {}", tag_group_handle.context);
     let tag_group = tagfile::TagGroup::from_table(tag_group_handle)?;
+    //'switch' only re-renders the groups that follow the active scheme.
+    if matches!(program_options.command, args::Command::Switch(_))
+        && tag_group.scheme_spec.scheme != state::ACTIVE_SCHEME
+    {
+        return Ok(RunSummary::default());
+    }
+    if let Some(reason) = tag_group
+        .when
+        .failed(tag_root)
+        .map_err(|e| configs::ConfigError::Path(tag_group_handle.context.with("when".to_owned()), e))?
+    {
+        eprintln!("[Info] Skipping group '{}' ({})", tag_group_handle.context, reason);
+        return Ok(RunSummary::default());
+    }
+    if !(program_options.dry_run || program_options.diff) {
+        run_hooks(
+            &tag_group.hooks.pre,
+            &tag_group_handle.context.with("hooks".to_owned()).with("pre".to_owned()),
+        )?;
+    }
+    let scheme_spec = effective_scheme_spec(tag_group.scheme_spec, program_options, state)?;
     let options = opt_basis.clone().overriden_by(tag_group.options);
     eprintln!(">> OPTIONS :: {:#?}", options);
     //cringe
+    let scheme = get_scheme(registry, &scheme_spec, &tag_group_handle.context)?;
+    let scheme_context = tag_group_handle.context.with("scheme".to_owned());
+    let scheme = match (scheme_spec.variant, &program_options.variant) {
+        (Some(variant), _) => Arc::new(scheme.with_variant(variant, &scheme_context)?),
+        (None, Some(variant)) if scheme.variants.contains_key(variant) => {
+            Arc::new(scheme.with_variant(variant, &scheme_context)?)
+        }
+        _ => scheme,
+    };
     let bindings = get_bindings(
-        &registry,
-        &tag_group.scheme_spec,
+        &scheme,
+        &scheme_spec,
         meta_opts,
         tag_group_handle.context.clone(),
     )?;
     eprintln!(">> BINDINGS :: {:#?}", bindings);
+    let mut summary = RunSummary::default();
     for file in tag_group.files {
-        let axbind_file = escaped_manip(
-            options.axbind_file_format.unwrap().as_str(),
-            options.escape_char.unwrap(),
-            |s| s.replace(meta_opts.wildcard_char.unwrap(), file)
-        );
-        let axbind_file_path = affecting_dir.with_file_name(&axbind_file);
-        let file_path = affecting_dir.with_file_name(file);
+        let (file_path, axbind_file_path) = match tag_root.file_paths(file.path, &options, meta_opts) {
+            Ok(paths) => paths,
+            Err(e) => {
+                eprintln!("[Warn] Unable to resolve paths for file '{}' (file skipped)", file.path);
+                eprint!(" - {}", e);
+                summary.skipped += 1;
+                continue;
+            }
+        };
         eprintln!(">> AFFECTING FILE :: {:?}", file_path);
         eprintln!(">> AXBIND FILE :: {:?}", axbind_file_path);
         let axbind_contents = match std::fs::read_to_string(&axbind_file_path) {
@@ -117,20 +215,266 @@ fn evaluate_taggroup<'a>(
                     axbind_file_path
                 );
                 eprintln!(" - {}", e);
+                summary.skipped += 1;
                 continue;
             }
         };
         //eprintln!(">> CONTENTS :: {}", axbind_contents);
-        if let Err(e) = std::fs::write(
-            file_path,
-            axbind_replace(axbind_contents.as_str(), &bindings, &options, &meta_opts)
-                .map_err(|e| MainError::ReplaceError(e))?
-                .as_str(),
+        let rendered = match axbind_replace(axbind_contents.as_str(), &bindings, &scheme, &options, &meta_opts) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("[Warn] Error rendering axbind file {:?} (file skipped)", axbind_file_path);
+                eprintln!(" - {}", e);
+                summary.skipped += 1;
+                continue;
+            }
+        };
+        if options.strict.unwrap() {
+            let unresolved =
+                match find_unresolved(axbind_contents.as_str(), &bindings, &options, meta_opts) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        eprintln!("[Warn] Error checking axbind file {:?} (file skipped)", axbind_file_path);
+                        eprintln!(" - {}", e);
+                        summary.skipped += 1;
+                        continue;
+                    }
+                };
+            if !unresolved.is_empty() {
+                eprintln!(
+                    "[Warn] Unresolved keys in axbind file {:?} (file skipped)",
+                    axbind_file_path
+                );
+                for key in unresolved {
+                    eprintln!(
+                        " - '{}' at {}:{}:{}",
+                        key.key,
+                        axbind_file_path.display(),
+                        key.line,
+                        key.column
+                    );
+                }
+                summary.skipped += 1;
+                continue;
+            }
+        }
+        let existing = match read_existing(&file_path) {
+            Ok(v) => v,
+            Err(e) => {
+                eprintln!("[Warn] Error reading file {:?} (file skipped)", file_path);
+                eprintln!(" - {}", e);
+                summary.skipped += 1;
+                continue;
+            }
+        };
+        let status = FileStatus::compare(existing.as_deref(), rendered.as_str());
+        if program_options.diff && status != FileStatus::Unchanged {
+            print!("{}", unified_diff(&file_path, existing.as_deref(), rendered.as_str()));
+        }
+        if program_options.dry_run {
+            println!("{:>9} :: {}", status, file_path.display());
+        }
+        if program_options.dry_run || program_options.diff {
+            summary.record(status);
+            continue;
+        }
+        let write_options = write::WriteOptions {
+            mode: file.mode,
+            ..(&options).into()
+        };
+        //rewriting identical contents would only bump the mtime (unless a declared mode differs).
+        let result = match status {
+            FileStatus::Unchanged
+                if file.mode.is_none() || write::current_mode(&file_path) == file.mode =>
+            {
+                Ok(())
+            }
+            _ => write::write_file(&file_path, rendered.as_str(), write_options),
+        };
+        if let Err(e) = result {
+            eprintln!("[Warn] Error writing to file '{}' (file skipped)", file.path);
+            eprintln!(" - {}", e);
+            summary.skipped += 1;
+            continue;
+        }
+        println!("{:>9} :: {}", status.done(), file_path.display());
+        summary.record(status);
+    }
+    if !(program_options.dry_run || program_options.diff) && summary.differences() {
+        //the files are already written, so the group's summary still counts.
+        if let Err(e) = run_hooks(
+            &tag_group.hooks.post,
+            &tag_group_handle.context.with("hooks".to_owned()).with("post".to_owned()),
         ) {
-            eprintln!("[Warn] Error writing to file '{}' (file skipped)", file);
+            eprintln!("[Warn] Post hook of group '{}' failed", tag_group_handle.context);
             eprintln!(" - {}", e);
+            summary.failed_hooks += 1;
+        }
+    }
+    Ok(summary)
+}
+///Re-renders the tag groups affected by changes to their scheme (or anything it extends/includes), tagfiles or axbind files.
+///Switching the active scheme re-renders the groups that follow it.
+///Tag directories are only searched for once, on startup.
+fn watch(
+    tagdir_paths: &[PathBuf],
+    master_config: &configs::MasterConfig,
+    mut registry: configs::SchemeRegistry,
+    program_options: &args::ProgramOptions,
+    mut state: state::LazyState,
+) -> Result<(), MainError> {
+    let state_path = state::State::path().ok();
+    let mut tag_roots = watch::TagRootCache::default();
+    let mut watcher = watch::MtimeWatcher::default();
+    watcher.poll(watched_paths(tagdir_paths, master_config, state_path.as_deref(), &mut tag_roots));
+    eprintln!("[Info] Watching {} tag directories for changes", tagdir_paths.len());
+    loop {
+        std::thread::sleep(watch::POLL_INTERVAL);
+        let changed =
+            watcher.poll(watched_paths(tagdir_paths, master_config, state_path.as_deref(), &mut tag_roots));
+        if changed.is_empty() {
+            continue;
+        }
+        eprintln!(" >> CHANGED :: {:?}", changed);
+        let scheme_files: Vec<PathBuf> = changed
+            .iter()
+            .filter(|p| master_config.scheme_dirs.iter().any(|dir| p.starts_with(dir)))
+            .cloned()
+            .collect();
+        let mut changed_schemes = Vec::new();
+        if !scheme_files.is_empty() {
+            //names from before the reload catch removed/renamed schemes.
+            changed_schemes = registry.names_in(&scheme_files);
+            registry = configs::SchemeRegistry::load_dirs(&master_config.scheme_dirs);
+            changed_schemes.extend(registry.names_in(&scheme_files));
+            changed_schemes = registry.with_dependents(changed_schemes);
+        }
+        if state_path.as_ref().is_some_and(|path| changed.contains(path)) {
+            state = state::LazyState::default();
+            changed_schemes.push(state::ACTIVE_SCHEME.to_owned());
+        }
+        for tag_path in tagdir_paths {
+            let tag_root = warn_continue!(tag_roots.get(tag_path),
+                format!("Unable to interpret tag directory {:?}", tag_path));
+            let tagfiles = tag_root.tagfile_paths();
+            let tagdir_changed =
+                changed.iter().any(|p| p.starts_with(tag_path) || tagfiles.contains(p));
+            for group in tag_root.group_tables() {
+                let handle = group.handle();
+                let affected = tagdir_changed
+                    || warn_continue!(
+                        group_affected(tag_root, &handle, master_config, program_options, &state, &changed_schemes, &changed),
+                        format!("Unable to apply group '{}'", group.context)
+                    );
+                if !affected {
+                    continue;
+                }
+                eprintln!("[Info] Re-rendering group '{}'", group.context);
+                warn_continue!(evaluate_taggroup(
+                    tag_root,
+                    &handle,
+                    &master_config.options,
+                    &registry,
+                    &master_config.meta_options,
+                    program_options,
+                    &state,
+                ),
+                format!("Unable to apply group '{}'", group.context));
+            }
         }
     }
+}
+///Whether a tag group's (effective) scheme is in 'changed_schemes' or one of its axbind files is in 'changed'.
+///'changed_schemes' has 'ACTIVE_SCHEME' once the state changed.
+fn group_affected(
+    tag_root: &tagfile::TagRoot,
+    handle: &TableHandle,
+    master_config: &configs::MasterConfig,
+    program_options: &args::ProgramOptions,
+    state: &state::LazyState,
+    changed_schemes: &[String],
+    changed: &[PathBuf],
+) -> Result<bool, MainError> {
+    let tag_group = tagfile::TagGroup::from_table(handle)?;
+    if changed_schemes.contains(tag_group.scheme_spec.scheme) {
+        return Ok(true);
+    }
+    let scheme_spec = effective_scheme_spec(tag_group.scheme_spec, program_options, state)?;
+    if changed_schemes.contains(scheme_spec.scheme) {
+        return Ok(true);
+    }
+    let options = master_config.options.clone().overriden_by(tag_group.options);
+    Ok(tag_group.files.iter().any(|file| {
+        tag_root
+            .file_paths(file.path, &options, &master_config.meta_options)
+            .is_ok_and(|(_, axbind_file)| changed.contains(&axbind_file))
+    }))
+}
+///Scheme files, tagfiles (including group files outside their tag directory), axbind files and the state file.
+fn watched_paths(
+    tagdir_paths: &[PathBuf],
+    master_config: &configs::MasterConfig,
+    state_path: Option<&Path>,
+    tag_roots: &mut watch::TagRootCache,
+) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = master_config
+        .scheme_dirs
+        .iter()
+        .flat_map(|dir| configs::SchemeRegistry::scheme_files(dir).unwrap_or_default())
+        .collect();
+    for tag_path in tagdir_paths {
+        paths.extend(watch::dir_files(tag_path));
+        let Ok(tag_root) = tag_roots.get(tag_path) else {
+            continue;
+        };
+        paths.extend(tag_root.tagfile_paths());
+        for group in tag_root.group_tables() {
+            let Ok(tag_group) = tagfile::TagGroup::from_table(&group.handle()) else {
+                continue;
+            };
+            let options = master_config.options.clone().overriden_by(tag_group.options);
+            paths.extend(tag_group.files.iter().filter_map(|file| {
+                tag_root
+                    .file_paths(file.path, &options, &master_config.meta_options)
+                    .ok()
+                    .map(|(_, axbind_file)| axbind_file)
+            }));
+        }
+    }
+    paths.extend(state_path.map(Path::to_path_buf));
+    //'main.toml' and group files inside the tag directory are listed twice.
+    paths.sort();
+    paths.dedup();
+    paths
+}
+fn check_all(
+    tagdir_paths: &[PathBuf],
+    registry: &configs::SchemeRegistry,
+    master_config: &configs::MasterConfig,
+    state: &state::LazyState,
+) -> Result<(), MainError> {
+    let mut problems = check::check_schemes(registry);
+    for tag_path in tagdir_paths {
+        problems.extend(check::check_tagdir(
+            tag_path,
+            registry,
+            &master_config.options,
+            &master_config.meta_options,
+            state,
+        ));
+    }
+    for problem in &problems {
+        match problem.is_warning() {
+            true => eprint!("[Warn] {}", problem),
+            false => eprint!("[Error] {}", problem),
+        }
+    }
+    let errors = problems.iter().filter(|p| !p.is_warning()).count();
+    if errors > 0 {
+        eprintln!(" >> {} PROBLEM(S) FOUND <<", errors);
+        exit(1);
+    }
+    eprintln!(" >> OK <<");
     Ok(())
 }
 fn main() {
@@ -0,0 +1,110 @@
+//! Resolution of path-valued settings ('scheme_dirs', tagfile 'groups', 'files', 'axbind_file_format').
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum PathError {
+    NoHome(String),
+    UnsetVar(String, String),
+}
+impl std::fmt::Display for PathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoHome(path) => writeln!(f, "Unable to expand '~' in '{}' ($HOME is not set)", path),
+            Self::UnsetVar(path, var) => writeln!(
+                f,
+                "Unable to expand '{}' in '{}' (environment variable is not set)",
+                var, path
+            ),
+        }
+    }
+}
+///Expands 'value' (see 'expand'), then resolves it against 'base_dir' if it is relative.
+///Absolute paths are taken as they are.
+pub fn resolve_path(value: &str, base_dir: &Path) -> Result<PathBuf, PathError> {
+    Ok(base_dir.join(expand(value)?))
+}
+///Expands a leading '~' and every '$VAR' or '${VAR}'.
+///A '$' not followed by a variable name is left as is.
+pub fn expand(value: &str) -> Result<String, PathError> {
+    let var = |name: &str| {
+        std::env::var(name).map_err(|_| PathError::UnsetVar(value.to_owned(), format!("${}", name)))
+    };
+    let mut o = String::with_capacity(value.len());
+    let mut rest = value;
+    if rest == "~" || rest.starts_with("~/") {
+        o.push_str(&std::env::var("HOME").map_err(|_| PathError::NoHome(value.to_owned()))?);
+        rest = &rest[1..];
+    }
+    while let Some(i) = rest.find('$') {
+        o.push_str(&rest[..i]);
+        let after = &rest[i + 1..];
+        let (name, len) = match after.strip_prefix('{') {
+            Some(braced) => match braced.find('}') {
+                Some(end) => (&braced[..end], end + 2),
+                None => ("", 0),
+            },
+            None => {
+                let end = after
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                    .unwrap_or(after.len());
+                (&after[..end], end)
+            }
+        };
+        if name.is_empty() {
+            o.push('$');
+            rest = after;
+            continue;
+        }
+        o.push_str(&var(name)?);
+        rest = &after[len..];
+    }
+    o.push_str(rest);
+    Ok(o)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_home() {
+        std::env::set_var("HOME", "/home/test");
+        assert_eq!(expand("~").unwrap(), "/home/test");
+        assert_eq!(expand("~/x").unwrap(), "/home/test/x");
+        //only a leading '~' is the home directory.
+        assert_eq!(expand("a/~/x").unwrap(), "a/~/x");
+        assert_eq!(expand("~x").unwrap(), "~x");
+    }
+    #[test]
+    fn expands_vars() {
+        std::env::set_var("AXBIND_PATHS_TEST_A", "alpha");
+        assert_eq!(expand("$AXBIND_PATHS_TEST_A/x").unwrap(), "alpha/x");
+        assert_eq!(expand("${AXBIND_PATHS_TEST_A}x").unwrap(), "alphax");
+        assert_eq!(expand("a-$AXBIND_PATHS_TEST_A-b").unwrap(), "a-alpha-b");
+    }
+    #[test]
+    fn unset_var_is_an_error() {
+        std::env::remove_var("AXBIND_PATHS_TEST_UNSET");
+        assert!(matches!(
+            expand("$AXBIND_PATHS_TEST_UNSET/x"),
+            Err(PathError::UnsetVar(_, var)) if var == "$AXBIND_PATHS_TEST_UNSET"
+        ));
+        assert!(matches!(
+            expand("${AXBIND_PATHS_TEST_UNSET}"),
+            Err(PathError::UnsetVar(..))
+        ));
+    }
+    #[test]
+    fn lone_dollar_is_literal() {
+        assert_eq!(expand("a$").unwrap(), "a$");
+        assert_eq!(expand("a$/b").unwrap(), "a$/b");
+        assert_eq!(expand("$$").unwrap(), "$$");
+        assert_eq!(expand("${unclosed").unwrap(), "${unclosed");
+    }
+    #[test]
+    fn resolves_against_base_dir() {
+        let base = Path::new("/etc/axbind");
+        assert_eq!(resolve_path("schemes", base).unwrap(), PathBuf::from("/etc/axbind/schemes"));
+        assert_eq!(resolve_path("../x", base).unwrap(), PathBuf::from("/etc/axbind/../x"));
+        assert_eq!(resolve_path("/opt/schemes", base).unwrap(), PathBuf::from("/opt/schemes"));
+    }
+}
@@ -0,0 +1,118 @@
+use crate::paths::{expand, PathError};
+use crate::{Path, PathBuf};
+use std::sync::OnceLock;
+use serde::{Deserialize, Serialize};
+
+///Tag groups with this scheme name use whichever scheme was last 'axbind switch'ed to.
+pub const ACTIVE_SCHEME: &str = "@active";
+
+#[derive(Debug)]
+pub enum StateError {
+    Path(PathError),
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, Box<toml::de::Error>),
+    Serialize(toml::ser::Error),
+    NoActive,
+    NoPrevious,
+}
+impl std::fmt::Display for StateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use StateError::*;
+        match self {
+            Path(e) => e.fmt(f),
+            Io(path, e) => {
+                writeln!(f, "Unable to access state file {:?}", path)?;
+                writeln!(f, " - {}", e)
+            }
+            Parse(path, e) => {
+                writeln!(f, "Invalid state file {:?}", path)?;
+                writeln!(f, " - {}", e)
+            }
+            Serialize(e) => writeln!(f, "Unable to serialize state: {}", e),
+            NoActive => writeln!(
+                f,
+                "Scheme '{}' used, but no scheme is active (see 'axbind switch')",
+                ACTIVE_SCHEME
+            ),
+            NoPrevious => writeln!(f, "No previously active scheme to switch back to"),
+        }
+    }
+}
+///Persisted between runs in 'state.toml' (see 'State::path').
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct State {
+    pub active: Option<String>,
+    pub previous: Option<String>,
+}
+impl State {
+    ///'$XDG_STATE_HOME/axbind/state.toml', falling back to '~/.local/state/axbind/state.toml'.
+    pub fn path() -> Result<PathBuf, StateError> {
+        let state_home = match std::env::var("XDG_STATE_HOME") {
+            Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(expand("~/.local/state").map_err(StateError::Path)?),
+        };
+        Ok(state_home.join("axbind").join("state.toml"))
+    }
+    ///A missing state file is an empty state.
+    pub fn load(path: &Path) -> Result<State, StateError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).map_err(|e| StateError::Parse(path.to_path_buf(), Box::new(e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(StateError::Io(path.to_path_buf(), e)),
+        }
+    }
+    pub fn save(&self, path: &Path) -> Result<(), StateError> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|e| StateError::Io(dir.to_path_buf(), e))?;
+        }
+        let text = toml::to_string(self).map_err(StateError::Serialize)?;
+        std::fs::write(path, text).map_err(|e| StateError::Io(path.to_path_buf(), e))
+    }
+    ///Resolves a 'switch' target; '-' is the previously active scheme.
+    pub fn target<'s>(&'s self, name: &'s str) -> Result<&'s str, StateError> {
+        match name {
+            "-" => self.previous.as_deref().ok_or(StateError::NoPrevious),
+            name => Ok(name),
+        }
+    }
+    pub fn switch(&mut self, name: String) {
+        if self.active.as_ref() != Some(&name) {
+            self.previous = self.active.replace(name);
+        }
+    }
+    ///Resolves 'ACTIVE_SCHEME' to the active scheme; other names are returned as-is.
+    pub fn resolve<'s>(&'s self, scheme: &'s String) -> Result<&'s String, StateError> {
+        match scheme.as_str() {
+            ACTIVE_SCHEME => self.active.as_ref().ok_or(StateError::NoActive),
+            _ => Ok(scheme),
+        }
+    }
+}
+///The state, read from 'State::path' the first time a tag group follows 'ACTIVE_SCHEME'.
+#[derive(Debug, Default)]
+pub struct LazyState {
+    state: OnceLock<State>,
+}
+impl LazyState {
+    pub fn get(&self) -> Result<&State, StateError> {
+        if let Some(state) = self.state.get() {
+            return Ok(state);
+        }
+        let state = State::load(&State::path()?)?;
+        Ok(self.state.get_or_init(|| state))
+    }
+    ///Like 'State::resolve', but other names never load the state.
+    pub fn resolve<'s>(&'s self, scheme: &'s String) -> Result<&'s String, StateError> {
+        match scheme.as_str() {
+            ACTIVE_SCHEME => self.get()?.resolve(scheme),
+            _ => Ok(scheme),
+        }
+    }
+}
+impl From<State> for LazyState {
+    fn from(state: State) -> Self {
+        LazyState {
+            state: OnceLock::from(state),
+        }
+    }
+}
@@ -1,7 +1,9 @@
+use crate::args::SchemeOverride;
 use crate::configs::*;
+use crate::paths::{resolve_path, PathError};
 use crate::{
-    extract_array_strings, extract_value, PathBuf, RootErr, TableGetError,
-    TableHandle, TableResultOptional, TableRoot,
+    escaped_manip, extract_array_strings, extract_value, PathBuf, PotentialValueHandle, RootErr,
+    TableGetError, TableHandle, TableResultOptional, TableRoot,
 };
 
 #[derive(Debug)]
@@ -9,24 +11,204 @@ pub struct SchemeSpec<'t> {
     pub scheme: &'t String,
     pub remaps: Vec<&'t String>,
     pub functions: Vec<&'t String>,
+    ///Takes precedence over '--variant'.
+    pub variant: Option<&'t String>,
+    ///'pinned = true' opts out of command line scheme overrides.
+    pub pinned: bool,
+}
+impl<'t> SchemeSpec<'t> {
+    ///Replaces the scheme and extends the remaps/functions with those of 'scheme_override' (unless pinned).
+    pub fn overriden_by<'a>(self, scheme_override: &'a SchemeOverride) -> SchemeSpec<'a>
+    where 't: 'a {
+        if self.pinned {
+            return self;
+        }
+        let SchemeSpec {
+            scheme,
+            mut remaps,
+            mut functions,
+            variant,
+            pinned,
+        } = self;
+        remaps.extend(&scheme_override.remaps);
+        functions.extend(&scheme_override.functions);
+        SchemeSpec {
+            scheme: scheme_override.scheme.as_ref().unwrap_or(scheme),
+            remaps,
+            functions,
+            variant,
+            pinned,
+        }
+    }
+}
+///Shell commands run (with 'sh -c') around rendering a tag group.
+#[derive(Debug, Default)]
+pub struct Hooks<'t> {
+    ///Run before rendering (not in dry-run/diff mode); a failure skips the group.
+    pub pre: Vec<&'t String>,
+    ///Run after rendering, only if at least one of the group's files changed.
+    pub post: Vec<&'t String>,
+}
+impl Hooks<'_> {
+    pub fn from_table<'t>(table: TableHandle<'t>) -> Result<Hooks<'t>, ConfigError> {
+        Ok(Hooks {
+            pre: extract_array_strings(table.get("pre"))
+                .optional()?
+                .unwrap_or(vec![]),
+            post: extract_array_strings(table.get("post"))
+                .optional()?
+                .unwrap_or(vec![]),
+        })
+    }
+}
+///'[when]' guards; a tag group is only rendered if all of them hold.
+#[derive(Debug, Default)]
+pub struct When<'t> {
+    pub hostname: Option<&'t String>,
+    ///Each must be set.
+    pub env_set: Vec<&'t String>,
+    ///'env = { VAR = "value" }'; each must be set to exactly its value.
+    pub env: Vec<(&'t String, &'t String)>,
+    ///Each must exist; relative paths are resolved like 'files'.
+    pub file_exists: Vec<&'t String>,
+    ///Must exit 0 (run with 'sh -c').
+    pub command: Option<&'t String>,
+}
+impl When<'_> {
+    pub fn from_table<'t>(table: TableHandle<'t>) -> Result<When<'t>, ConfigError> {
+        let env = match extract_value!(Table, table.get("env")).optional()? {
+            Some(env_table) => env_table
+                .clone()
+                .into_iter()
+                .map(|(var, value)| Ok((var, extract_value!(String, value)?)))
+                .collect::<Result<Vec<_>, ConfigError>>()?,
+            None => vec![],
+        };
+        Ok(When {
+            hostname: extract_value!(String, table.get("hostname")).optional()?,
+            env_set: extract_array_strings(table.get("env_set"))
+                .optional()?
+                .unwrap_or(vec![]),
+            env,
+            file_exists: extract_array_strings(table.get("file_exists"))
+                .optional()?
+                .unwrap_or(vec![]),
+            command: extract_value!(String, table.get("command")).optional()?,
+        })
+    }
+    ///Returns a description of the first guard that does not hold, if any.
+    pub fn failed(&self, tag_root: &TagRoot) -> Result<Option<String>, PathError> {
+        if let Some(hostname) = self.hostname {
+            let actual = current_hostname();
+            if actual.as_ref() != Some(hostname) {
+                return Ok(Some(format!(
+                    "hostname is '{}', not '{}'",
+                    actual.unwrap_or_default(),
+                    hostname
+                )));
+            }
+        }
+        if let Some(var) = self.env_set.iter().find(|var| std::env::var_os(var).is_none()) {
+            return Ok(Some(format!("${} is not set", var)));
+        }
+        for (var, value) in &self.env {
+            if std::env::var(var).ok().as_ref() != Some(*value) {
+                return Ok(Some(format!("${} is not '{}'", var, value)));
+            }
+        }
+        for file in &self.file_exists {
+            if !resolve_path(file, &tag_root.affecting_dir())?.exists() {
+                return Ok(Some(format!("'{}' does not exist", file)));
+            }
+        }
+        if let Some(command) = self.command {
+            let success = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .status()
+                .is_ok_and(|status| status.success());
+            if !success {
+                return Ok(Some(format!("command '{}' failed", command)));
+            }
+        }
+        Ok(None)
+    }
+}
+fn current_hostname() -> Option<String> {
+    let hostname = match std::fs::read_to_string("/proc/sys/kernel/hostname") {
+        Ok(hostname) => hostname,
+        Err(_) => String::from_utf8(
+            std::process::Command::new("uname")
+                .arg("-n")
+                .output()
+                .ok()?
+                .stdout,
+        )
+        .ok()?,
+    };
+    Some(hostname.trim().to_owned())
+}
+///An entry of a tag group's 'files': either a path, or '{ path = "...", mode = "0600" }'.
+#[derive(Debug)]
+pub struct FileSpec<'t> {
+    pub path: &'t String,
+    ///Permission bits applied to the written file; defaults to the tag group's 'mode'.
+    pub mode: Option<u32>,
+}
+impl FileSpec<'_> {
+    fn from_value<'t>(
+        handle: PotentialValueHandle<'t>,
+        group_mode: Option<u32>,
+    ) -> Result<FileSpec<'t>, ConfigError> {
+        if let Ok(path) = extract_value!(String, handle.clone()) {
+            return Ok(FileSpec {
+                path,
+                mode: group_mode,
+            });
+        }
+        let table = extract_value!(Table, handle)?;
+        Ok(FileSpec {
+            path: extract_value!(String, table.get("path"))?,
+            mode: extract_mode(table.get("mode"))?.or(group_mode),
+        })
+    }
+}
+///Parses an octal mode string such as "0755".
+fn extract_mode(handle: PotentialValueHandle) -> Result<Option<u32>, ConfigError> {
+    let raw = match extract_value!(String, handle.clone()).optional()? {
+        Some(raw) => raw,
+        None => return Ok(None),
+    };
+    match u32::from_str_radix(raw.trim_start_matches("0o"), 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(Some(mode)),
+        _ => Err(ConfigError::Misc(format!(
+            "value for '{}' must be an octal mode such as \"0644\" (got '{}')",
+            handle.context, raw
+        ))),
+    }
 }
 #[derive(Debug)]
 pub struct TagGroup<'t> {
-    pub files: Vec<&'t String>,
+    pub files: Vec<FileSpec<'t>>,
     pub scheme_spec: SchemeSpec<'t>,
     pub options: Options<'t>,
+    pub hooks: Hooks<'t>,
+    pub when: When<'t>,
 }
 #[derive(Debug)]
 pub struct TagRoot {
     pub path: PathBuf,
     pub main: TableRoot,
     pub groups: Option<Vec<TableRoot>>,
+    ///Where each of 'groups' was read from (may be outside the tag directory).
+    pub group_paths: Vec<PathBuf>,
 }
 #[derive(Debug)]
 pub enum GenerateErr {
     Root(RootErr),
     TableGet(TableGetError),
     FilesAndGroupExist(PathBuf),
+    Path(PathError),
 }
 impl std::fmt::Display for GenerateErr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -38,6 +220,7 @@ impl std::fmt::Display for GenerateErr {
                 "'main' tagfiles must have either a 'files' or 'groups' key, not both. ({:?})",
                 path
             ),
+            Self::Path(e) => e.fmt(f),
         }
     }
 }
@@ -51,31 +234,87 @@ impl TagRoot {
         let group_paths = extract_array_strings(main.handle().get("groups"))
             .optional()
             .map_err(|e| GenerateErr::TableGet(e))?;
-        let groups = match group_paths {
-            Some(gvec) => Some(
-                gvec.into_iter()
-                    .map(|gpath| TableRoot::from_file_path(path.with_file_name(gpath)))
-                    .collect::<Result<Vec<TableRoot>, RootErr>>()
-                    .map_err(|e| GenerateErr::Root(e))?,
+        let group_paths = group_paths
+            .unwrap_or_default()
+            .into_iter()
+            .map(|gpath| resolve_path(gpath, dir_path.as_ref()).map_err(GenerateErr::Path))
+            .collect::<Result<Vec<PathBuf>, GenerateErr>>()?;
+        let groups = match main.table.contains_key("groups") {
+            true => Some(
+                group_paths
+                    .iter()
+                    .map(|group_path| TableRoot::from_file_path(group_path).map_err(GenerateErr::Root))
+                    .collect::<Result<Vec<TableRoot>, GenerateErr>>()?,
             ),
-            None => None,
+            false => None,
         };
         if main.table.get("files").is_some() && groups.is_some() {
             return Err(GenerateErr::FilesAndGroupExist(
                 path.with_file_name("main.toml"),
             ));
         }
-        Ok(TagRoot { main, groups, path })
+        Ok(TagRoot { main, groups, group_paths, path })
+    }
+    ///The tables that define tag groups; 'main' itself if there are no 'groups'.
+    pub fn group_tables(&self) -> Vec<&TableRoot> {
+        match &self.groups {
+            Some(groups) => groups.iter().collect(),
+            None => vec![&self.main],
+        }
+    }
+    ///'main.toml' and every group file.
+    pub fn tagfile_paths(&self) -> Vec<PathBuf> {
+        let mut paths = vec![self.path.clone()];
+        paths.extend(self.group_paths.iter().cloned());
+        paths
+    }
+    ///The directory containing the tag directory.
+    pub fn affecting_dir(&self) -> PathBuf {
+        let mut affecting_dir = self.path.clone();
+        affecting_dir.pop();
+        affecting_dir.pop();
+        affecting_dir
+    }
+    ///Returns (target file path, axbind file path) for an entry of a tag group's 'files'.
+    ///Relative paths are resolved against the directory containing the tag directory.
+    pub fn file_paths(
+        &self,
+        file: &str,
+        options: &Options,
+        meta_opts: &MetaOptions,
+    ) -> Result<(PathBuf, PathBuf), PathError> {
+        let affecting_dir = self.affecting_dir();
+        let axbind_file = escaped_manip(
+            options.axbind_file_format.unwrap().as_str(),
+            options.escape_char.unwrap(),
+            |s| s.replace(meta_opts.wildcard_char.unwrap(), file),
+        );
+        Ok((
+            resolve_path(file, &affecting_dir)?,
+            resolve_path(&axbind_file, &affecting_dir)?,
+        ))
     }
 }
 impl TagGroup<'_> {
     pub fn from_table<'t>(table: &TableHandle<'t>) -> Result<TagGroup<'t>, ConfigError> {
-        let files = extract_array_strings(table.get("files"))?;
+        let group_mode = extract_mode(table.get("mode"))?;
+        let files = extract_value!(Array, table.get("files"))?
+            .into_iter()
+            .map(|file| FileSpec::from_value(file, group_mode))
+            .collect::<Result<Vec<FileSpec>, ConfigError>>()?;
         let scheme_table = extract_value!(Table, table.get("scheme"))?;
         let options = match extract_value!(Table, table.get("options")).optional()? {
             Some(option_table) => Options::from_table(option_table)?,
             None => Options::default(),
         };
+        let hooks = match extract_value!(Table, table.get("hooks")).optional()? {
+            Some(hooks_table) => Hooks::from_table(hooks_table)?,
+            None => Hooks::default(),
+        };
+        let when = match extract_value!(Table, table.get("when")).optional()? {
+            Some(when_table) => When::from_table(when_table)?,
+            None => When::default(),
+        };
         let remaps = extract_array_strings(scheme_table.get("remaps"))
             .optional()?
             .unwrap_or(vec![]);
@@ -83,15 +322,24 @@ impl TagGroup<'_> {
             .optional()?
             .unwrap_or(vec![]);
         let scheme = extract_value!(String, scheme_table.get("name"))?;
+        let variant = extract_value!(String, scheme_table.get("variant")).optional()?;
+        let pinned = extract_value!(Boolean, scheme_table.get("pinned"))
+            .optional()?
+            .copied()
+            .unwrap_or(false);
         let scheme_spec = SchemeSpec {
             scheme,
             remaps,
             functions,
+            variant,
+            pinned,
         };
         Ok(TagGroup {
             files,
             options,
             scheme_spec,
+            hooks,
+            when,
         })
     }
 }
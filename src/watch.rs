@@ -0,0 +1,64 @@
+use crate::tagfile::{GenerateErr, TagRoot};
+use crate::{HashMap, Path, PathBuf};
+use std::time::SystemTime;
+
+///How often 'axbind watch' checks for changes.
+pub const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+///Detects changes by comparing modification times between polls.
+#[derive(Debug, Default)]
+pub struct MtimeWatcher {
+    mtimes: HashMap<PathBuf, Option<SystemTime>>,
+}
+impl MtimeWatcher {
+    ///Returns the paths that were created, modified or removed since the last poll.
+    ///Only 'paths' are watched from now on; paths no longer given count as removed.
+    pub fn poll(&mut self, paths: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut mtimes = HashMap::with_capacity(paths.len());
+        let mut changed = Vec::new();
+        for path in paths {
+            let mtime = std::fs::metadata(&path).and_then(|m| m.modified()).ok();
+            match self.mtimes.remove(&path) {
+                Some(old) if old == mtime => (),
+                _ => changed.push(path.clone()),
+            }
+            mtimes.insert(path, mtime);
+        }
+        changed.extend(self.mtimes.drain().map(|(path, _)| path));
+        self.mtimes = mtimes;
+        changed
+    }
+}
+///Every file directly inside 'dir' (none if it cannot be read).
+pub fn dir_files(dir: &Path) -> Vec<PathBuf> {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+///Parsed tag directories, kept until one of their tagfiles changes.
+#[derive(Debug, Default)]
+pub struct TagRootCache {
+    roots: HashMap<PathBuf, (TagRoot, MtimeWatcher)>,
+}
+impl TagRootCache {
+    ///The tag directory at 'tag_path', re-read only if it is new or any of its tagfiles changed.
+    pub fn get(&mut self, tag_path: &Path) -> Result<&TagRoot, GenerateErr> {
+        let unchanged = self
+            .roots
+            .get_mut(tag_path)
+            .is_some_and(|(tag_root, watcher)| watcher.poll(tag_root.tagfile_paths()).is_empty());
+        if !unchanged {
+            self.roots.remove(tag_path);
+            let tag_root = TagRoot::generate_from_dir(tag_path)?;
+            let mut watcher = MtimeWatcher::default();
+            watcher.poll(tag_root.tagfile_paths());
+            self.roots.insert(tag_path.to_path_buf(), (tag_root, watcher));
+        }
+        Ok(&self.roots[tag_path].0)
+    }
+}
@@ -0,0 +1,169 @@
+use crate::configs::Options;
+use crate::{Path, PathBuf};
+use std::fs;
+use std::io::{self, Write};
+
+///How a rendered file replaces its target (see the corresponding 'Options' fields).
+#[derive(Debug, Clone, Copy)]
+pub struct WriteOptions {
+    pub atomic: bool,
+    pub preserve_mode: bool,
+    pub preserve_owner: bool,
+    pub follow_symlinks: bool,
+    ///Permission bits to give the target (overrides 'preserve_mode').
+    pub mode: Option<u32>,
+}
+impl From<&Options<'_>> for WriteOptions {
+    ///'options' must have its write settings filled (as by 'MasterConfig').
+    fn from(options: &Options) -> Self {
+        WriteOptions {
+            atomic: options.atomic_write.unwrap(),
+            preserve_mode: options.preserve_mode.unwrap(),
+            preserve_owner: options.preserve_owner.unwrap(),
+            follow_symlinks: options.follow_symlinks.unwrap(),
+            mode: None,
+        }
+    }
+}
+///Writes 'contents' to 'path' as described by 'options'.
+///A declared or preserved mode is set before anything is written; other new files get the usual '0o666 & !umask'.
+pub fn write_file(path: &Path, contents: &str, options: WriteOptions) -> io::Result<()> {
+    let target = match options.follow_symlinks {
+        true => resolve_symlinks(path)?,
+        false => path.to_path_buf(),
+    };
+    //a symlink left at 'target' (with 'follow_symlinks' off) is replaced like a missing file.
+    let symlink_metadata = fs::symlink_metadata(&target).ok();
+    let existing = symlink_metadata
+        .as_ref()
+        .filter(|m| !m.file_type().is_symlink());
+    if !options.atomic {
+        //writing in place would go through the link.
+        if existing.is_none() && symlink_metadata.is_some() {
+            fs::remove_file(&target)?;
+        }
+        return write_in_place(&target, contents, options.mode);
+    }
+    let mode = match (options.mode, existing) {
+        (Some(mode), _) => Some(mode),
+        (None, Some(existing)) if options.preserve_mode => permission_bits(existing),
+        _ => None,
+    };
+    let temp = temp_path(&target);
+    let result = (|| {
+        let mut file = open_with_mode(&temp, true, mode)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        if let Some(existing) = existing {
+            if options.preserve_owner && !copy_owner(existing, &temp)? {
+                //only the target itself still has the right owner.
+                eprintln!(
+                    "[Warn] Unable to give a replacement the owner of {:?} (written in place instead)",
+                    target
+                );
+                fs::remove_file(&temp)?;
+                return write_in_place(&target, contents, options.mode);
+            }
+        }
+        //replaces a symlink at 'target' itself, rather than its target.
+        fs::rename(&temp, &target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+///Truncates (or creates) 'path' and writes 'contents' to it; an existing file keeps its mode unless 'mode' is given.
+fn write_in_place(path: &Path, contents: &str, mode: Option<u32>) -> io::Result<()> {
+    open_with_mode(path, false, mode)?.write_all(contents.as_bytes())
+}
+///Opens 'path' for writing ('create_new' if 'new', otherwise truncating it).
+///'mode' (if any) is set before anything is written.
+fn open_with_mode(path: &Path, new: bool, mode: Option<u32>) -> io::Result<fs::File> {
+    let mut open_options = fs::OpenOptions::new();
+    match new {
+        true => open_options.write(true).create_new(true),
+        false => open_options.write(true).create(true).truncate(true),
+    };
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        open_options.mode(mode);
+    }
+    let file = open_options.open(path)?;
+    //the mode given on creation is masked by the umask (and ignored for existing files).
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(file)
+}
+///The permission bits of 'path', if it exists (always 'None' on non-unix).
+pub fn current_mode(path: &Path) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::metadata(path).ok().map(|m| m.permissions().mode() & 0o7777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        None
+    }
+}
+///The permission bits of 'metadata' (always 'None' on non-unix).
+fn permission_bits(metadata: &fs::Metadata) -> Option<u32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        Some(metadata.permissions().mode() & 0o7777)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        None
+    }
+}
+///Follows 'path' through any chain of symlinks; the final target need not exist.
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    //same limit as linux's ELOOP.
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(m) if m.file_type().is_symlink() => {
+                let link = fs::read_link(&path)?;
+                path = path.parent().unwrap_or(Path::new("")).join(link);
+            }
+            _ => return Ok(path),
+        }
+    }
+    Err(io::Error::other(format!("too many levels of symbolic links: {:?}", path)))
+}
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    target.with_file_name(format!(".{}.axbind-{}.tmp", name, std::process::id()))
+}
+///Returns false (and leaves 'path' alone) if the owner cannot be changed (e.g. without privileges).
+#[cfg(unix)]
+fn copy_owner(from: &fs::Metadata, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let current = fs::metadata(path)?;
+    if current.uid() == from.uid() && current.gid() == from.gid() {
+        return Ok(true);
+    }
+    match std::os::unix::fs::chown(path, Some(from.uid()), Some(from.gid())) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+#[cfg(not(unix))]
+fn copy_owner(_from: &fs::Metadata, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}